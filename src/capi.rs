@@ -0,0 +1,109 @@
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    ptr
+};
+use {run_str_capture, Error};
+
+/// The program ran successfully
+pub const LCI_OK: c_int = 0;
+/// The source was a null pointer or not valid UTF-8
+pub const LCI_INVALID_INPUT: c_int = 1;
+/// The source could not be tokenized
+pub const LCI_TOKENIZE_ERROR: c_int = 2;
+/// The source could not be parsed
+pub const LCI_PARSE_ERROR: c_int = 3;
+/// The program failed while running
+pub const LCI_EVAL_ERROR: c_int = 4;
+
+fn to_c_string(string: String) -> CString {
+    // C strings can't hold NUL, so output stops at the first one
+    CString::new(string).unwrap_or_else(|err| {
+        let nul = err.nul_position();
+        let mut bytes = err.into_vec();
+        bytes.truncate(nul);
+        CString::new(bytes).unwrap()
+    })
+}
+
+/// Run the NUL-terminated LOLCODE source `src` without any input.
+///
+/// If `out` isn't null, it's set to a newly allocated string which must be
+/// released with `lci_free`: everything the program printed on success, or
+/// the error message otherwise. It's set to null if `src` is invalid.
+///
+/// # Safety
+/// `src` must be null or point to a NUL-terminated string, and `out` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lci_run(src: *const c_char, out: *mut *mut c_char) -> c_int {
+    if !out.is_null() {
+        *out = ptr::null_mut();
+    }
+    if src.is_null() {
+        return LCI_INVALID_INPUT;
+    }
+    let code = match CStr::from_ptr(src).to_str() {
+        Ok(code) => code,
+        Err(_) => return LCI_INVALID_INPUT
+    };
+    let (status, output) = match run_str_capture(code) {
+        Ok(output) => (LCI_OK, output),
+        Err(err) => (match err {
+            Error::TokenizeError(_) => LCI_TOKENIZE_ERROR,
+            Error::ParseError(_) => LCI_PARSE_ERROR,
+            Error::EvalError(_) | Error::IoError(_) | Error::InFile(..) => LCI_EVAL_ERROR
+        }, err.to_string())
+    };
+    if !out.is_null() {
+        *out = to_c_string(output).into_raw();
+    }
+    status
+}
+
+/// Free a string returned by `lci_run`. Does nothing if `ptr` is null.
+///
+/// # Safety
+/// `ptr` must be null or a string from `lci_run` that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lci_free(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        drop(CString::from_raw(ptr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(src: &[u8]) -> (c_int, Option<String>) {
+        unsafe {
+            let mut out = ptr::null_mut();
+            let status = lci_run(src.as_ptr() as *const c_char, &mut out);
+            if out.is_null() {
+                return (status, None);
+            }
+            let output = CStr::from_ptr(out).to_str().unwrap().to_string();
+            lci_free(out);
+            (status, Some(output))
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(run(b"VISIBLE \"HAI \" 1\0"), (LCI_OK, Some("HAI 1\n".to_string())));
+        assert_eq!(run(b"VISIBLE \"A:(0)B\"\0"), (LCI_OK, Some("A".to_string())));
+        assert_eq!(run(b"VISIBLE 1.2.3\0").0, LCI_TOKENIZE_ERROR);
+        assert_eq!(run(b"VISIBLE SUM OF 1\0").0, LCI_PARSE_ERROR);
+        assert_eq!(
+            run(b"VISIBLE QUOSHUNT OF 1 AN 0\0"),
+            (LCI_EVAL_ERROR, Some("eval error: attempt to divide by zero".to_string()))
+        );
+        assert_eq!(run(b"VISIBLE \"\xFF\"\0"), (LCI_INVALID_INPUT, None));
+        unsafe {
+            assert_eq!(lci_run(ptr::null(), ptr::null_mut()), LCI_INVALID_INPUT);
+            assert_eq!(lci_run(b"VISIBLE 1\0".as_ptr() as *const c_char, ptr::null_mut()), LCI_OK);
+            lci_free(ptr::null_mut());
+        }
+    }
+}
@@ -1,14 +1,27 @@
 use parser::{AST, Expr, Operation};
+use rng::{LazyRng, RngCore, SharedRng};
 use std::{
-    cell::RefCell,
-    collections::HashMap,
+    cell::{Ref, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
     io,
+    mem,
+    rc::Rc,
     result::Result as StdResult
 };
-use types::Value;
+use tokenizer::Located;
+use types::{Type, Value};
 
 #[derive(Debug, Fail)]
 pub enum Error {
+    #[fail(display = "arithmetic overflow")]
+    ArithmeticOverflow,
+    #[fail(display = "assertion failed: {}", _0)]
+    AssertionFailed(String),
+    #[fail(display = "cannot cast {} to {}", from, to)]
+    BadCast { from: Type, to: Type },
+    #[fail(display = "BUKKIT nesting limit reached: can't go more than {} levels deep", _0)]
+    DepthLimit(usize),
     #[fail(display = "attempt to divide by zero")]
     DivideByZero,
     #[fail(display = "cannot cast value to that type")]
@@ -17,14 +30,28 @@ pub enum Error {
     InvalidCastLoop,
     #[fail(display = "function {:?} expected {} parameters", _0, _1)]
     InvalidUsage(String, usize),
+    #[fail(display = "function {:?} expected {} to {} parameters, got {}", _0, _1, _2, _3)]
+    InvalidUsageRange(String, usize, usize, usize),
+    #[fail(display = "can't access slot {:?} of something that isn't a BUKKIT", _0)]
+    NotABukkit(String),
+    #[fail(display = "can't assign to {}", _0)]
+    NotAPlace(Expr),
+    #[fail(display = "type error in {}", _0)]
+    TypeError(Expr),
     #[fail(display = "io error: {}", _0)]
     IoError(io::Error),
+    #[fail(display = "{} is not permitted", _0)]
+    IoNotPermitted(&'static str),
+    #[fail(display = "index {} is out of range for length {}", _0, _1)]
+    IndexOutOfRange(i64, usize),
     #[fail(display = "recursion limit reached: can't go more than {} levels deep", _0)]
     RecursionLimit(usize),
     #[fail(display = "can't shadow variable from the same scope: {:?}", _0)]
     ShadowVar(String),
     #[fail(display = "undefined function {:?}", _0)]
     UndefinedFunc(String),
+    #[fail(display = "undefined slot {:?}", _0)]
+    UndefinedSlot(String),
     #[fail(display = "undefined variable {:?}", _0)]
     UndefinedVar(String)
 }
@@ -37,75 +64,291 @@ pub enum Return {
     Value(Value)
 }
 
+type Callback = Box<dyn FnMut(Vec<Value>) -> Result<Value>>;
+type AssignHook = Box<dyn FnMut(&str, &Value)>;
+
+/// What to do when NUMBR arithmetic overflows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArithMode {
+    /// Fail with `Error::ArithmeticOverflow`
+    #[default]
+    Checked,
+    /// Wrap around, like two's complement hardware
+    Wrap,
+    /// Clamp to the smallest or biggest NUMBR
+    Saturate
+}
+impl ArithMode {
+    fn apply(self, x: i64, y: i64, checked: fn(i64, i64) -> Option<i64>,
+             wrapping: fn(i64, i64) -> i64, saturating: fn(i64, i64) -> i64) -> Result<i64> {
+        match self {
+            ArithMode::Checked => checked(x, y).ok_or(Error::ArithmeticOverflow),
+            ArithMode::Wrap => Ok(wrapping(x, y)),
+            ArithMode::Saturate => Ok(saturating(x, y))
+        }
+    }
+}
+
+/// Which statements a script may use to talk to the outside world
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IoPolicy {
+    /// Neither GIMMEH nor VISIBLE/INVISIBLE
+    AllowNone,
+    /// VISIBLE and INVISIBLE, but not GIMMEH
+    AllowOutput,
+    #[default]
+    AllowAll
+}
+
 struct Function {
     args: Vec<String>,
-    block: Vec<AST>
+    block: Rc<[AST]>
 }
 
 /// Parameters global to the whole evaluation
-pub struct EvalParams<R: io::BufRead, W: io::Write> {
+pub struct EvalParams<R: io::BufRead, W: io::Write, E: io::Write = io::Stderr, T: io::Write = io::Sink> {
     stdin: R,
     stdout: W,
+    stderr: E,
+    input_queue: VecDeque<String>,
 
-    funcs: HashMap<String, (Option<usize>, Box<FnMut(Vec<Value>) -> Value>)>,
+    funcs: HashMap<String, (Option<usize>, Callback)>,
+    vars: HashMap<String, Value>,
+    on_assign: AssignHook,
+    rng: SharedRng,
+    arith_mode: ArithMode,
+    io_policy: IoPolicy,
     recursion_limit: usize,
-    recursion: usize
+    recursion: usize,
+    depth_limit: usize,
+    /// The line and column of the statement being executed
+    position: Option<(usize, usize)>,
+    trace: Option<T>,
+    /// If the last trace line is still waiting for its statement to finish
+    trace_open: bool
 }
 impl<R: io::BufRead, W: io::Write> EvalParams<R, W> {
+    /// Create new parameters. GIMMEH reads lines from `stdin` and VISIBLE
+    /// writes to `stdout`, so any reader/writer can be plugged in.
+    /// INVISIBLE writes to the process' stderr, see `with_error_output`.
     pub fn new(stdin: R, stdout: W) -> Self {
         Self {
-            stdin: stdin,
-            stdout: stdout,
+            stdin,
+            stdout,
+            stderr: io::stderr(),
+            input_queue: VecDeque::new(),
 
             funcs: HashMap::new(),
+            vars: HashMap::new(),
+            on_assign: Box::new(|_, _| ()),
+            rng: Rc::new(RefCell::new(Box::new(LazyRng::default()))),
+            arith_mode: ArithMode::default(),
+            io_policy: IoPolicy::default(),
             recursion_limit: 64,
-            recursion: 0
+            recursion: 0,
+            depth_limit: 256,
+            position: None,
+            trace: None,
+            trace_open: false
         }
     }
-    /// Set the recursion limit
+}
+impl EvalParams<io::Empty, Vec<u8>> {
+    /// Create new parameters with no input, capturing all output in
+    /// memory. See `take_output`.
+    pub fn captured() -> Self {
+        Self::new(io::empty(), Vec::new())
+    }
+}
+impl<R: io::BufRead, E: io::Write, T: io::Write> EvalParams<R, Vec<u8>, E, T> {
+    /// Retrieve and clear everything printed so far
+    pub fn take_output(&mut self) -> String {
+        String::from_utf8(mem::take(&mut self.stdout)).expect("Program (somehow) returned non-utf8 data")
+    }
+}
+impl<R: io::BufRead, W: io::Write, E: io::Write, T: io::Write> EvalParams<R, W, E, T> {
+    /// Replace the writer INVISIBLE prints to
+    pub fn with_error_output<E2: io::Write>(self, stderr: E2) -> EvalParams<R, W, E2, T> {
+        self.with_writers(|_, trace| (stderr, trace))
+    }
+    /// Alias for `with_error_output`
+    pub fn with_stderr<E2: io::Write>(self, stderr: E2) -> EvalParams<R, W, E2, T> {
+        self.with_error_output(stderr)
+    }
+    /// Log each statement to `trace` as it's executed, including ones
+    /// inside loops and functions, followed by the value of IT afterwards
+    /// or the error it failed with. Statements spanning several lines only
+    /// show their first one. Programs from `parser::parse_located` also get
+    /// line numbers.
+    pub fn with_trace<T2: io::Write>(self, trace: T2) -> EvalParams<R, W, E, T2> {
+        self.with_writers(|stderr, _| (stderr, Some(trace)))
+    }
+    fn with_writers<E2, T2, F>(self, writers: F) -> EvalParams<R, W, E2, T2>
+        where E2: io::Write,
+              T2: io::Write,
+              F: FnOnce(E, Option<T>) -> (E2, Option<T2>)
+    {
+        let (stderr, trace) = writers(self.stderr, self.trace);
+        EvalParams {
+            stdin: self.stdin,
+            stdout: self.stdout,
+            stderr,
+            input_queue: self.input_queue,
+
+            funcs: self.funcs,
+            vars: self.vars,
+            on_assign: self.on_assign,
+            rng: self.rng,
+            arith_mode: self.arith_mode,
+            io_policy: self.io_policy,
+            recursion_limit: self.recursion_limit,
+            recursion: self.recursion,
+            depth_limit: self.depth_limit,
+            position: self.position,
+            trace,
+            trace_open: self.trace_open
+        }
+    }
+    /// Write the start of a trace line, ending the previous one if its
+    /// statement is still running
+    fn trace_start(&mut self, line: &str, statement: &str) -> io::Result<()> {
+        let open = mem::replace(&mut self.trace_open, true);
+        if let Some(ref mut trace) = self.trace {
+            if open {
+                writeln!(trace)?;
+            }
+            write!(trace, "{}{}", line, statement)?;
+        }
+        Ok(())
+    }
+    /// Write how a traced statement went. If other statements were traced
+    /// while it ran, this goes on a new line.
+    fn trace_end(&mut self, line: &str, outcome: &str) -> io::Result<()> {
+        let open = mem::replace(&mut self.trace_open, false);
+        if let Some(ref mut trace) = self.trace {
+            if open {
+                writeln!(trace, " => {}", outcome)?;
+            } else {
+                writeln!(trace, "{}... => {}", line, outcome)?;
+            }
+        }
+        Ok(())
+    }
+    /// Queue up lines for GIMMEH to read, in order, before it
+    /// falls back to reading from stdin
+    pub fn queue_input(&mut self, lines: Vec<String>) {
+        self.input_queue.extend(lines);
+    }
+    /// Set what happens when NUMBR arithmetic overflows
+    pub fn set_arith_mode(&mut self, mode: ArithMode) {
+        self.arith_mode = mode;
+    }
+    /// Restrict which IO statements are permitted, e.g. for untrusted scripts
+    pub fn set_io_policy(&mut self, policy: IoPolicy) {
+        self.io_policy = policy;
+    }
+    /// Set how many LOLCODE function calls deep a program may go, 64 by default
     pub fn set_recursion_limit(&mut self, limit: usize) {
         self.recursion_limit = limit;
     }
+    /// Set how many levels deep BUKKITs may be nested, 256 by default.
+    /// Comparing BUKKITs recurses, so this keeps that from overflowing the stack.
+    pub fn set_depth_limit(&mut self, limit: usize) {
+        self.depth_limit = limit;
+    }
     /// Bind a LOLCODE function to a rust closure
-    pub fn bind_func<S, F>(&mut self, name: S, args: Option<usize>, func: F)
+    pub fn bind_func<S, F>(&mut self, name: S, args: Option<usize>, mut func: F)
         where S: Into<String>,
               F: FnMut(Vec<Value>) -> Value + 'static
+    {
+        self.try_bind_func(name, args, move |values| Ok(func(values)));
+    }
+    /// Bind a LOLCODE function to a rust closure which may fail,
+    /// stopping the program with the error
+    pub fn try_bind_func<S, F>(&mut self, name: S, args: Option<usize>, func: F)
+        where S: Into<String>,
+              F: FnMut(Vec<Value>) -> Result<Value> + 'static
     {
         self.funcs.insert(name.into(), (args, Box::new(func)));
     }
+    /// Call `hook` whenever a variable is declared or assigned, including IT.
+    /// Assigning to a slot passes the whole place, e.g. `B'Z X`.
+    pub fn on_assign<F>(&mut self, hook: F)
+        where F: FnMut(&str, &Value) + 'static
+    {
+        self.on_assign = Box::new(hook);
+    }
+    /// Replace the random number generator, e.g. with a seeded one
+    /// to make programs reproducible
+    pub fn set_rng<G: RngCore + 'static>(&mut self, rng: G) {
+        *self.rng.borrow_mut() = Box::new(rng);
+    }
+    /// The random number generator. Native functions needing randomness
+    /// should hold on to this, so `set_rng` affects them too.
+    pub fn rng(&self) -> SharedRng {
+        Rc::clone(&self.rng)
+    }
+    /// Set the variables the top-level scope starts out with.
+    /// Scripts can both read and modify these.
+    pub fn set_working_scope(&mut self, vars: HashMap<String, Value>) {
+        self.vars = vars;
+    }
     /// Create a new top-level scope with this evaluator.
     /// Use the return value of this to evaluate AST.
-    pub fn scope<'a>(self) -> Scope<'a, R, W> {
+    pub fn scope<'a>(mut self) -> Scope<'a, R, W, E, T> {
+        let vars = mem::take(&mut self.vars);
         Scope {
             params: Some(RefCell::new(self)),
 
-            it: RefCell::new(Value::Noob),
-            vars: RefCell::new(HashMap::new()),
+            it: Some(RefCell::new(Value::Noob)),
+            vars: RefCell::new(vars),
             funcs: RefCell::new(HashMap::new()),
             parent: None
         }
     }
 }
 
+/// Only clone the operands of a math operator when it fails
+fn type_error(expr: fn(Box<Expr>, Box<Expr>) -> Expr, one: &Expr, two: &Expr) -> Error {
+    Error::TypeError(expr(Box::new(one.clone()), Box::new(two.clone())))
+}
+/// How many BUKKITs deep a slot of the place `bukkit` is, e.g. 2 for `X'Z Y`
+fn place_depth(bukkit: &Expr) -> usize {
+    match *bukkit {
+        Expr::Slot(ref bukkit, _) => place_depth(bukkit) + 1,
+        _ => 1
+    }
+}
+
+fn print<T: io::Write>(output: &mut T, text: &str, newline: bool) -> Result<()> {
+    output.write_all(text.as_bytes()).map_err(Error::IoError)?;
+    if newline {
+        output.write_all(b"\n").map_err(Error::IoError)
+    } else {
+        output.flush().map_err(Error::IoError)
+    }
+}
+
 /// Parameters local to the current scope
-pub struct Scope<'a, R: io::BufRead + 'a, W: io::Write + 'a> {
-    params: Option<RefCell<EvalParams<R, W>>>,
+pub struct Scope<'a, R: io::BufRead + 'a, W: io::Write + 'a, E: io::Write + 'a = io::Stderr, T: io::Write + 'a = io::Sink> {
+    params: Option<RefCell<EvalParams<R, W, E, T>>>,
 
-    it: RefCell<Value>,
+    /// Only functions (and the top-level scope) have their own IT
+    it: Option<RefCell<Value>>,
     vars: RefCell<HashMap<String, Value>>,
     funcs: RefCell<HashMap<String, Function>>,
-    parent: Option<&'a Scope<'a, R, W>>
+    parent: Option<&'a Scope<'a, R, W, E, T>>
 }
-impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
-    pub fn params(&self) -> &RefCell<EvalParams<R, W>> {
+impl<'a, R: io::BufRead, W: io::Write, E: io::Write, T: io::Write> Scope<'a, R, W, E, T> {
+    pub fn params(&self) -> &RefCell<EvalParams<R, W, E, T>> {
         let mut me = self;
         while let Some(parent) = me.parent {
             me = parent;
         }
         me.params.as_ref().expect("Missing 'params' on top-level scope")
     }
-    pub fn find_var<F, T>(&self, name: &str, apply: F) -> Option<T>
-        where F: FnOnce(&mut Value) -> T
+    pub fn find_var<F, O>(&self, name: &str, apply: F) -> Option<O>
+        where F: FnOnce(&mut Value) -> O
     {
         let mut me = self;
         loop {
@@ -131,142 +374,210 @@ impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
                         return Err(Error::InvalidUsage(name.to_string(), nargs));
                     }
                 }
-                return Ok(func(args));
+                return func(args);
             }
 
             // Prevent stack overflow
-            params.recursion += 1;
-            if params.recursion > params.recursion_limit {
+            if params.recursion >= params.recursion_limit {
                 return Err(Error::RecursionLimit(params.recursion_limit));
             }
+            params.recursion += 1;
         }
+        // Errors end the call too, so the scope can still be used afterwards
+        let result = self.call_defined_func(name, args);
+        self.params().borrow_mut().recursion -= 1;
+        result
+    }
+    fn call_defined_func(&self, name: &str, args: Vec<Value>) -> Result<Value> {
         // Traverse down scopes
         let mut me = self;
-        Ok(loop {
-            let block = match me.funcs.borrow_mut().get_mut(name) {
+        loop {
+            let func = match me.funcs.borrow().get(name) {
                 None => None,
                 Some(the_func) => {
                     if args.len() != the_func.args.len() {
                         return Err(Error::InvalidUsage(name.to_string(), the_func.args.len()));
                     }
-
-                    let mut vars = me.vars.borrow_mut();
-                    for (i, arg) in args.iter().enumerate() {
-                        vars.insert(the_func.args[i].clone(), arg.clone());
-                    }
-                    Some(the_func.block.clone())
+                    Some((the_func.args.clone(), the_func.block.clone()))
                 }
             };
-            if let Some(block) = block {
-                let scope = me.scope();
-                let val = match scope.eval_all(block)? {
-                    Return::None => scope.it.borrow().clone(),
+            if let Some((names, block)) = func {
+                // Arguments live in the call's own scope, shadowing any outer variables
+                let scope = me.function_scope();
+                for (name, arg) in names.into_iter().zip(args) {
+                    scope.assigned(&name, &arg);
+                    scope.vars.borrow_mut().insert(name, arg);
+                }
+                return Ok(match scope.eval_block(&block)? {
+                    Return::None => scope.it().borrow().clone(),
                     Return::Gtfo => Value::Noob,
                     Return::Value(val) => val
-                };
-                {
-                    let params = &mut me.params();
-                    params.borrow_mut().recursion -= 1;
-                }
-                break val;
+                });
             } else if let Some(parent) = me.parent {
                 me = parent;
             } else {
                 return Err(Error::UndefinedFunc(name.to_string()));
             }
-        })
+        }
     }
+    /// The IT variable of the surrounding function
+    pub fn it(&self) -> &RefCell<Value> {
+        let mut me = self;
+        loop {
+            match (&me.it, me.parent) {
+                (Some(it), _) => break it,
+                (None, Some(parent)) => me = parent,
+                (None, None) => unreachable!("top-level scope always has IT")
+            }
+        }
+    }
+    /// Create a block scope, sharing IT with this one
     pub fn scope(&'a self) -> Self {
         Self {
             params: None,
 
-            it: self.it.clone(),
+            it: None,
             vars: RefCell::new(HashMap::new()),
             funcs: RefCell::new(HashMap::new()),
             parent: Some(self)
         }
     }
+    /// Create a scope for a function call, with its own IT
+    fn function_scope(&'a self) -> Self {
+        Self {
+            it: Some(RefCell::new(Value::Noob)),
+            ..self.scope()
+        }
+    }
 
-    fn apply_num<F1, F2>(&self, one: Expr, two: Expr, if_numbr: F1, if_numbar: F2) -> Result<Value>
+    fn apply_num<F1, F2>(&self, expr: fn(Box<Expr>, Box<Expr>) -> Expr, one: &Expr, two: &Expr,
+                         if_numbr: F1, if_numbar: F2) -> Result<Value>
         where F1: FnOnce(i64, i64) -> Result<i64>,
               F2: FnOnce(f64, f64) -> f64
     {
-        let one = self.eval_expr(one)?;
-        let two = self.eval_expr(two)?;
-        if one.is_numbar() || two.is_numbar() {
-            Ok(Value::Numbar(if_numbar(
-                one.cast_numbar().ok_or(Error::InvalidCast)?,
-                two.cast_numbar().ok_or(Error::InvalidCast)?
-            )))
-        } else {
-            Ok(Value::Numbr(if_numbr(
-                one.cast_numbr().ok_or(Error::InvalidCast)?,
-                two.cast_numbr().ok_or(Error::InvalidCast)?
-            )?))
-        }
-    }
-    fn apply_any<F>(&self, one: Expr, two: Expr, apply: F) -> Result<Value>
+        let x = self.eval_expr(one)?;
+        let y = self.eval_expr(two)?;
+        match x.coerce_numeric_pair(&y) {
+            Some((Value::Numbr(x), Value::Numbr(y))) => Ok(Value::Numbr(if_numbr(x, y)?)),
+            Some((Value::Numbar(x), Value::Numbar(y))) => Ok(Value::Numbar(if_numbar(x, y))),
+            _ => Err(type_error(expr, one, two))
+        }
+    }
+    /// Pick BIGGR or SMALLR of two operands: the first one unless it compares
+    /// as `replace_if` to the second one
+    fn apply_extreme(&self, expr: fn(Box<Expr>, Box<Expr>) -> Expr, one: &Expr, two: &Expr,
+                     replace_if: Ordering) -> Result<Value>
+    {
+        let x = self.eval_expr(one)?;
+        let y = self.eval_expr(two)?;
+        match x.coerce_numeric_pair(&y) {
+            Some((x, y)) => Ok(match x.numeric_cmp(&y) {
+                Some(order) if order == replace_if => y,
+                Some(_) => x,
+                // NaN, so do what f64::max and f64::min do and prefer the number
+                None => match x {
+                    Value::Numbar(n) if n.is_nan() => y,
+                    _ => x
+                }
+            }),
+            None => Err(type_error(expr, one, two))
+        }
+    }
+    fn apply_any<F>(&self, one: &Expr, two: &Expr, apply: F) -> Result<Value>
         where F: FnOnce(Value, Value) -> bool
     {
         Ok(Value::Troof(apply(self.eval_expr(one)?, self.eval_expr(two)?)))
     }
-    fn apply_bool<F>(&self, one: Expr, two: Expr, apply: F) -> Result<Value>
+    fn apply_bool<F>(&self, one: &Expr, two: &Expr, apply: F) -> Result<Value>
         where F: FnOnce(bool, bool) -> bool
     {
         self.apply_any(one, two, |x, y| apply(x.cast_troof(), y.cast_troof()))
     }
-    pub fn eval_expr(&self, expr: Expr) -> Result<Value> {
+    pub fn eval_expr(&self, expr: &Expr) -> Result<Value> {
+        let mode = self.params().borrow().arith_mode;
         macro_rules! apply_num {
-            ($one:expr, $two:expr, $func:ident, $op:tt) => {
-                self.apply_num(*$one, *$two, |x, y| Ok(x.$func(y)), |x, y| x $op y)
+            ($what:path, $one:expr, $two:expr, $checked:path, $wrapping:path, $saturating:path, $op:tt) => {
+                self.apply_num($what, $one, $two, |x, y| mode.apply(x, y, $checked, $wrapping, $saturating), |x, y| x $op y)
             }
         }
-        match expr {
-            Expr::It => Ok(self.it.borrow().clone()),
-            Expr::Value(mut val) => {
+        match *expr {
+            Expr::It => Ok(self.it().borrow().clone()),
+            Expr::Value(ref val) => {
+                let mut val = val.clone();
                 if let Some(missing) = val.interpolate(|var| self.find_var(var, |var| var.clone())) {
                     return Err(Error::UndefinedVar(missing));
                 }
                 Ok(val)
             },
-            Expr::Var(ident) => {
-                if let Some(val) = self.find_var(&ident, |var| var.clone()) {
-                    return Ok(val);
+            Expr::Var(ref ident) => {
+                if let Some(val) = self.find_var(ident, |var| var.clone()) {
+                    Ok(val)
                 } else {
-                    return Err(Error::UndefinedVar(ident));
+                    Err(Error::UndefinedVar(ident.clone()))
+                }
+            },
+            Expr::Srs(ref name) => {
+                let ident = self.srs_name(name)?;
+                self.eval_expr(&Expr::Var(ident))
+            },
+            Expr::Slot(ref bukkit, ref key) => match **bukkit {
+                // Read slots of variables where they are, so only the slot is cloned
+                Expr::Var(_) | Expr::Srs(_) | Expr::Slot(..) => {
+                    let mut value = None;
+                    self.with_place(expr, &mut |slot| {
+                        value = Some(slot.clone());
+                        Ok(())
+                    })?;
+                    Ok(value.expect("with_place calls apply when it succeeds"))
+                },
+                _ => match self.eval_expr(bukkit)? {
+                    Value::Bukkit(mut slots) => match slots.iter().position(|(name, _)| name == key) {
+                        Some(i) => Ok(slots.swap_remove(i).1),
+                        None => Err(Error::UndefinedSlot(key.clone()))
+                    },
+                    _ => Err(Error::NotABukkit(key.clone()))
                 }
             },
-            Expr::IIz(name, args) => {
+            Expr::Maek(ref inner, kind) => {
+                let val = self.eval_expr(inner)?;
+                val.cast(kind).ok_or(Error::BadCast { from: val.kind(), to: kind })
+            },
+            Expr::IIz(ref name, ref args) => {
                 let mut args_val = Vec::with_capacity(args.len());
                 for arg in args {
                     args_val.push(self.eval_expr(arg)?);
                 }
-                self.call_func(&name, args_val)
+                self.call_func(name, args_val)
             },
 
-            Expr::SumOf(one, two) => apply_num!(one, two, wrapping_add, +),
-            Expr::DiffOf(one, two) => apply_num!(one, two, wrapping_sub, -),
-            Expr::ProduktOf(one, two) => apply_num!(one, two, wrapping_mul, *),
-            Expr::QuoshuntOf(one, two) => self.apply_num(*one, *two, |x, y| {
+            Expr::SumOf(ref one, ref two) =>
+                apply_num!(Expr::SumOf, one, two, i64::checked_add, i64::wrapping_add, i64::saturating_add, +),
+            Expr::DiffOf(ref one, ref two) =>
+                apply_num!(Expr::DiffOf, one, two, i64::checked_sub, i64::wrapping_sub, i64::saturating_sub, -),
+            Expr::ProduktOf(ref one, ref two) =>
+                apply_num!(Expr::ProduktOf, one, two, i64::checked_mul, i64::wrapping_mul, i64::saturating_mul, *),
+            // Dividing the smallest NUMBR by -1 overflows too
+            Expr::QuoshuntOf(ref one, ref two) => self.apply_num(Expr::QuoshuntOf, one, two, |x, y| {
                 if y == 0 {
                     return Err(Error::DivideByZero);
                 }
-                Ok(x / y)
+                mode.apply(x, y, i64::checked_div, i64::wrapping_div, i64::saturating_div)
             }, |x, y| x / y),
-            Expr::ModOf(one, two) => self.apply_num(*one, *two, |x, y| {
+            Expr::ModOf(ref one, ref two) => self.apply_num(Expr::ModOf, one, two, |x, y| {
                 if y == 0 {
                     return Err(Error::DivideByZero);
                 }
-                Ok(x % y)
+                // The remainder is 0 either way
+                mode.apply(x, y, i64::checked_rem, i64::wrapping_rem, i64::wrapping_rem)
             }, |x, y| x % y),
-            Expr::BiggrOf(one, two) => self.apply_num(*one, *two, |x, y| Ok(x.max(y)), |x, y| x.max(y)),
-            Expr::SmallrOf(one, two) => self.apply_num(*one, *two, |x, y| Ok(x.min(y)), |x, y| x.min(y)),
-
-            Expr::BothOf(one, two) => self.apply_bool(*one, *two, |x, y| x && y),
-            Expr::EitherOf(one, two) => self.apply_bool(*one, *two, |x, y| x || y),
-            Expr::WonOf(one, two) => self.apply_bool(*one, *two, |x, y| x ^ y),
-            Expr::Not(inner) => Ok(Value::Troof(!self.eval_expr(*inner)?.cast_troof())),
-            Expr::AllOf(values) => {
+            Expr::BiggrOf(ref one, ref two) => self.apply_extreme(Expr::BiggrOf, one, two, Ordering::Less),
+            Expr::SmallrOf(ref one, ref two) => self.apply_extreme(Expr::SmallrOf, one, two, Ordering::Greater),
+
+            Expr::BothOf(ref one, ref two) => self.apply_bool(one, two, |x, y| x && y),
+            Expr::EitherOf(ref one, ref two) => self.apply_bool(one, two, |x, y| x || y),
+            Expr::WonOf(ref one, ref two) => self.apply_bool(one, two, |x, y| x ^ y),
+            Expr::Not(ref inner) => Ok(Value::Troof(!self.eval_expr(inner)?.cast_troof())),
+            Expr::AllOf(ref values) => {
                 for value in values {
                     if !self.eval_expr(value)?.cast_troof() {
                         return Ok(Value::Troof(false))
@@ -274,7 +585,7 @@ impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
                 }
                 Ok(Value::Troof(true))
             },
-            Expr::AnyOf(values) => {
+            Expr::AnyOf(ref values) => {
                 for value in values {
                     if self.eval_expr(value)?.cast_troof() {
                         return Ok(Value::Troof(true))
@@ -283,10 +594,12 @@ impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
                 Ok(Value::Troof(false))
             },
 
-            Expr::BothSaem(one, two) => self.apply_any(*one, *two, |x, y| x.equals(&y)),
-            Expr::Diffrint(one, two) => self.apply_any(*one, *two, |x, y| !x.equals(&y)),
+            Expr::BothSaem(ref one, ref two) =>
+                Ok(Value::Troof(self.equals(&self.eval_expr(one)?, &self.eval_expr(two)?)?)),
+            Expr::Diffrint(ref one, ref two) =>
+                Ok(Value::Troof(!self.equals(&self.eval_expr(one)?, &self.eval_expr(two)?)?)),
 
-            Expr::Smoosh(exprs) => {
+            Expr::Smoosh(ref exprs) => {
                 let mut result = String::new();
                 for expr in exprs {
                     result.push_str(&self.eval_expr(expr)?.cast_yarn().ok_or(Error::InvalidCast)?);
@@ -295,103 +608,261 @@ impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
             }
         }
     }
+    fn assigned(&self, name: &str, value: &Value) {
+        (self.params().borrow_mut().on_assign)(name, value);
+    }
+    /// Run `apply` on the value a variable or slot expression refers to
+    fn with_place(&self, place: &Expr, apply: &mut dyn FnMut(&mut Value) -> Result<()>) -> Result<()> {
+        match *place {
+            Expr::Var(ref ident) => match self.find_var(ident, |var| apply(var)) {
+                Some(result) => result,
+                None => Err(Error::UndefinedVar(ident.clone()))
+            },
+            Expr::Srs(ref name) => {
+                let ident = self.srs_name(name)?;
+                self.with_place(&Expr::Var(ident), apply)
+            },
+            Expr::Slot(ref bukkit, ref key) => self.with_place(bukkit, &mut |bukkit| match *bukkit {
+                Value::Bukkit(ref mut slots) => match slots.iter_mut().find(|(name, _)| name == key) {
+                    Some((_, slot)) => apply(slot),
+                    None => Err(Error::UndefinedSlot(key.clone()))
+                },
+                _ => Err(Error::NotABukkit(key.clone()))
+            }),
+            // The parser never makes these, but the AST can be built by hand
+            _ => Err(Error::NotAPlace(place.clone()))
+        }
+    }
+    /// Fail if BUKKITs would be nested `depth` levels deep, see `set_depth_limit`
+    fn check_depth(&self, depth: usize) -> Result<()> {
+        let limit = self.params().borrow().depth_limit;
+        if depth > limit {
+            return Err(Error::DepthLimit(limit));
+        }
+        Ok(())
+    }
+    /// Compare values like BOTH SAEM. Comparing BUKKITs recurses, so
+    /// they're checked against the depth limit first.
+    fn equals(&self, x: &Value, y: &Value) -> Result<bool> {
+        if let (Value::Bukkit(_), Value::Bukkit(_)) = (x, y) {
+            self.check_depth(x.depth().max(y.depth()))?;
+        }
+        Ok(x.equals(y))
+    }
+    /// Resolve the variable name an `SRS` expression refers to
+    fn srs_name(&self, name: &Expr) -> Result<String> {
+        match self.eval_expr(name)?.cast_yarn() {
+            Some(name) => Ok(name.into_owned()),
+            None => Err(Error::InvalidCast)
+        }
+    }
+    fn visible_text(&self, exprs: &[Expr]) -> Result<String> {
+        let mut result = String::new();
+        for expr in exprs {
+            // NOOB has no YARN representation, so it prints as nothing
+            if let Some(yarn) = self.eval_expr(expr)?.cast_yarn() {
+                result.push_str(&yarn);
+            }
+        }
+        Ok(result)
+    }
     pub fn eval(&self, ast: AST) -> Result<Return> {
-        match ast {
-            AST::IHasA(ident, expr) => {
+        self.eval_ref(&ast)
+    }
+    fn eval_ref(&self, ast: &AST) -> Result<Return> {
+        // Function definitions are hoisted, so they would show up twice
+        let traced = match *ast {
+            AST::Located(_) | AST::HowIzI(..) => false,
+            _ => self.params().borrow().trace.is_some()
+        };
+        if !traced {
+            return self.eval_statement(ast);
+        }
+        let line = match self.params().borrow().position {
+            Some((line, _)) => format!("{}: ", line),
+            None => String::new()
+        };
+        let statement = ast.to_string();
+        let statement = statement.lines().next().unwrap_or("");
+        self.params().borrow_mut().trace_start(&line, statement).map_err(Error::IoError)?;
+        let result = self.eval_statement(ast);
+        let outcome = match result {
+            Ok(_) => format!("IT = {}", self.it().borrow().debug_lolcode()),
+            Err(ref err) => format!("error: {}", err)
+        };
+        self.params().borrow_mut().trace_end(&line, &outcome).map_err(Error::IoError)?;
+        result
+    }
+    fn eval_statement(&self, ast: &AST) -> Result<Return> {
+        match *ast {
+            AST::IHasA(ref ident, ref expr) => {
                 let val = self.eval_expr(expr)?;
                 {
                     let mut vars = self.vars.borrow_mut();
-                    if vars.contains_key(&ident) {
-                        return Err(Error::ShadowVar(ident));
+                    if vars.contains_key(ident) {
+                        return Err(Error::ShadowVar(ident.clone()));
                     }
-                    vars.insert(ident, val);
+                    vars.insert(ident.clone(), val.clone());
+                }
+                self.assigned(ident, &val);
+            },
+            AST::R(ref ident, ref expr) => {
+                let val = self.eval_expr(expr)?;
+                if self.find_var(ident, |var| *var = val.clone()).is_none() {
+                    return Err(Error::UndefinedVar(ident.clone()));
                 }
+                self.assigned(ident, &val);
             },
-            AST::R(ident, expr) => {
+            AST::RSrs(ref name, ref expr) => {
+                let ident = self.srs_name(name)?;
                 let val = self.eval_expr(expr)?;
-                if self.find_var(&ident, |var| *var = val).is_none() {
+                if self.find_var(&ident, |var| *var = val.clone()).is_none() {
                     return Err(Error::UndefinedVar(ident));
                 }
+                self.assigned(&ident, &val);
             },
-            AST::It(expr) => {
+            AST::RSlot(ref bukkit, ref key, ref expr) => {
+                let val = self.eval_expr(expr)?;
+                // Every BUKKIT the slot is in ends up deeper than the value
+                self.check_depth(place_depth(bukkit) + val.depth())?;
+                let place = format!("{}'Z {}", bukkit, key);
+                // Assigning to a slot that doesn't exist yet creates it
+                self.with_place(bukkit, &mut |bukkit| match *bukkit {
+                    Value::Bukkit(ref mut slots) => {
+                        match slots.iter_mut().find(|(name, _)| name == key) {
+                            Some((_, slot)) => *slot = val.clone(),
+                            None => slots.push((key.clone(), val.clone()))
+                        }
+                        Ok(())
+                    },
+                    _ => Err(Error::NotABukkit(key.clone()))
+                })?;
+                self.assigned(&place, &val);
+            },
+            AST::It(ref expr) => {
                 let expr = self.eval_expr(expr)?;
-                *self.it.borrow_mut() = expr;
+                self.assigned("IT", &expr);
+                *self.it().borrow_mut() = expr;
             },
-            AST::ORly(yarly, mebbe, nowai) => {
-                if self.it.borrow().cast_troof() {
-                    return self.eval_scope(yarly);
+            AST::ORly(ref yarly, ref mebbe, ref nowai) => {
+                if self.it().borrow().cast_troof() {
+                    return self.scope().eval_block(yarly);
                 }
                 for (condition, block) in mebbe {
                     if self.eval_expr(condition)?.cast_troof() {
-                        return self.eval_scope(block);
+                        return self.scope().eval_block(block);
                     }
                 }
-                return self.eval_scope(nowai);
+                return self.scope().eval_block(nowai);
             },
-            AST::Wtf(omg, omgwtf) => {
+            AST::Wtf(ref omg, ref omgwtf) => {
                 let mut matched = false;
-                let it = self.it.borrow();
+                // Blocks may change IT while we're still matching against it
+                let it = self.it().borrow().clone();
                 for (condition, block) in omg {
-                    if matched || *it == self.eval_expr(condition)? {
+                    // Cases match like BOTH SAEM does, so OMG 3.0 matches 3
+                    if matched || self.equals(&it, &self.eval_expr(condition)?)? {
                         matched = true;
-                        match self.eval_scope(block)? {
+                        match self.scope().eval_block(block)? {
                             Return::None => (),
                             Return::Gtfo => return Ok(Return::None),
                             val @ Return::Value(_) => return Ok(val)
                         }
                     }
                 }
-                return self.eval_scope(omgwtf);
+                // GTFO only leaves the WTF?, not any surrounding loop
+                return match self.scope().eval_block(omgwtf)? {
+                    Return::Gtfo => Ok(Return::None),
+                    ret => Ok(ret)
+                };
             },
-            AST::ImInYr(operation, var, condition, block) => {
-                let mut scope = self.scope();
+            AST::ImInYr(ref operation, ref var, ref condition, ref block) => {
+                // The condition is checked before each pass, and the operation
+                // is applied after the body
+                let mode = self.params().borrow().arith_mode;
+                let scope = self.scope();
                 scope.vars.borrow_mut().insert(var.clone(), Value::Numbr(0));
-                while condition.is_none() || scope.eval_expr(condition.clone().unwrap())?.cast_troof() {
-                    match scope.eval_scope(block.clone())? {
+                scope.assigned(var, &Value::Numbr(0));
+                while condition.is_none() || scope.eval_expr(condition.as_ref().unwrap())?.cast_troof() {
+                    match scope.scope().eval_block(block)? {
                         Return::None => (),
                         Return::Gtfo => return Ok(Return::None),
                         val @ Return::Value(_) => return Ok(val)
                     }
-                    let val = scope.vars.borrow_mut()[&var].clone();
-                    let val = match operation {
-                        Operation::Uppin => Value::Numbr(val.cast_numbr().ok_or(Error::InvalidCastLoop)? + 1),
-                        Operation::Nerfin => Value::Numbr(val.cast_numbr().ok_or(Error::InvalidCastLoop)? - 1),
-                        Operation::IIz(ref name) => scope.call_func(&name, vec![val])?
+                    let val = scope.vars.borrow_mut()[var].clone();
+                    let val = match *operation {
+                        // The body may have assigned anything to the variable
+                        Operation::Uppin => {
+                            let n = val.cast_numbr().ok_or(Error::InvalidCastLoop)?;
+                            Value::Numbr(mode.apply(n, 1, i64::checked_add, i64::wrapping_add, i64::saturating_add)?)
+                        },
+                        Operation::Nerfin => {
+                            let n = val.cast_numbr().ok_or(Error::InvalidCastLoop)?;
+                            Value::Numbr(mode.apply(n, 1, i64::checked_sub, i64::wrapping_sub, i64::saturating_sub)?)
+                        },
+                        Operation::IIz(ref name) => scope.call_func(name, vec![val])?
                     };
-                    *scope.vars.borrow_mut().get_mut(&var).unwrap() = val;
+                    scope.assigned(var, &val);
+                    *scope.vars.borrow_mut().get_mut(var).unwrap() = val;
                 }
             },
-            AST::HowIzI(name, args, block) => {
-                self.funcs.borrow_mut().insert(name, Function {
-                    args: args,
-                    block: block
+            AST::HowIzI(ref name, ref args, ref block) => {
+                self.funcs.borrow_mut().insert(name.clone(), Function {
+                    args: args.clone(),
+                    block: block.as_slice().into()
                 });
             },
 
             AST::Gtfo => return Ok(Return::Gtfo),
-            AST::FoundYr(expr) => return Ok(Return::Value(self.eval_expr(expr)?)),
+            AST::FoundYr(ref expr) => return Ok(Return::Value(self.eval_expr(expr)?)),
 
-            AST::Visible(exprs, newline) => {
-                let mut result = String::new();
-                for expr in exprs {
-                    result.push_str(&self.eval_expr(expr)?.cast_yarn().ok_or(Error::InvalidCast)?);
+            AST::Visible(ref exprs, newline) => {
+                if self.params().borrow().io_policy == IoPolicy::AllowNone {
+                    return Err(Error::IoNotPermitted("VISIBLE"));
                 }
-                let stdout = &mut self.params().borrow_mut().stdout;
-                stdout.write_all(result.as_bytes()).map_err(|err| Error::IoError(err))?;
-                if newline {
-                    stdout.write_all(b"\n").map_err(|err| Error::IoError(err))?;
-                } else {
-                    stdout.flush().map_err(|err| Error::IoError(err))?;
+                let result = self.visible_text(exprs)?;
+                print(&mut self.params().borrow_mut().stdout, &result, newline)?;
+            },
+            AST::Invisible(ref exprs, newline) => {
+                if self.params().borrow().io_policy == IoPolicy::AllowNone {
+                    return Err(Error::IoNotPermitted("INVISIBLE"));
                 }
+                let result = self.visible_text(exprs)?;
+                print(&mut self.params().borrow_mut().stderr, &result, newline)?;
             },
-            AST::Gimmeh(ident) => {
-                let stdin = &mut self.params().borrow_mut().stdin;
-
+            AST::Located(ref located) => {
+                let Located { line, col, ref inner, .. } = **located;
+                let outer = self.params().borrow_mut().position.replace((line, col));
+                let result = self.eval_ref(inner)?;
+                // After an error, the position stays at the statement that failed
+                self.params().borrow_mut().position = outer;
+                return Ok(result);
+            },
+            AST::Gimmeh(ref ident) => {
+                if self.params().borrow().io_policy != IoPolicy::AllowAll {
+                    return Err(Error::IoNotPermitted("GIMMEH"));
+                }
                 let mut text = String::new();
-                stdin.read_line(&mut text).map_err(|err| Error::IoError(err))?;
+                {
+                    let mut params = self.params().borrow_mut();
+                    match params.input_queue.pop_front() {
+                        Some(line) => text = line,
+                        None => { params.stdin.read_line(&mut text).map_err(Error::IoError)?; }
+                    }
+                }
 
+                // Variables holding a number keep being numbers
                 let text = text.trim().to_string();
-                self.vars.borrow_mut().insert(ident, Value::Yarn(text));
+                let val = Value::Yarn(text);
+                let val = match self.find_var(ident, |var| var.kind()) {
+                    Some(kind @ Type::Numbr) | Some(kind @ Type::Numbar) =>
+                        val.cast(kind).ok_or(Error::BadCast { from: Type::Yarn, to: kind })?,
+                    _ => val
+                };
+                self.assigned(ident, &val);
+                if self.find_var(ident, |var| *var = val.clone()).is_none() {
+                    self.vars.borrow_mut().insert(ident.clone(), val);
+                }
             }
         }
         Ok(Return::None)
@@ -401,15 +872,18 @@ impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
         where I: Iterator<Item = AST> + Clone,
               IN: IntoIterator<Item = AST, IntoIter = I>
     {
-        let iter = asts.into_iter();
-        for ast in iter.clone() {
-            if let AST::HowIzI(..) = ast {
+        let asts: Vec<AST> = asts.into_iter().collect();
+        self.eval_block(&asts)
+    }
+    fn eval_block(&self, asts: &[AST]) -> Result<Return> {
+        for ast in asts {
+            if let AST::HowIzI(..) = *ast.unlocated() {
                 // Pre-process function calls
-                self.eval(ast)?;
+                self.eval_ref(ast)?;
             }
         }
-        for ast in iter {
-            match self.eval(ast)? {
+        for ast in asts {
+            match self.eval_ref(ast)? {
                 Return::None => (),
                 val => return Ok(val)
             }
@@ -424,3 +898,923 @@ impl<'a, R: io::BufRead, W: io::Write> Scope<'a, R, W> {
         self.scope().eval_all(asts)
     }
 }
+
+/// What's left to do after `Interpreter::step`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepResult {
+    /// There are more statements to run
+    Continue,
+    /// The program has stopped
+    Finished
+}
+
+/// Runs a top-level program one statement at a time, e.g. for debuggers
+pub struct Interpreter<'a, R: io::BufRead + 'a, W: io::Write + 'a, E: io::Write + 'a = io::Stderr, T: io::Write + 'a = io::Sink> {
+    scope: Scope<'a, R, W, E, T>,
+    program: Vec<AST>,
+    pc: usize
+}
+impl<'a, R: io::BufRead, W: io::Write, E: io::Write, T: io::Write> Interpreter<'a, R, W, E, T> {
+    pub fn new(params: EvalParams<R, W, E, T>, program: Vec<AST>) -> Self {
+        Self {
+            scope: params.scope(),
+            program,
+            pc: 0
+        }
+    }
+    /// Log each statement to `trace` as it's executed, see `EvalParams::with_trace`
+    pub fn with_trace<T2: io::Write + 'a>(self, trace: T2) -> Interpreter<'a, R, W, E, T2> {
+        let Scope { params, it, vars, funcs, .. } = self.scope;
+        let params = params.expect("the interpreter has a top-level scope").into_inner();
+        Interpreter {
+            scope: Scope {
+                params: Some(RefCell::new(params.with_trace(trace))),
+                it,
+                vars,
+                funcs,
+                parent: None
+            },
+            program: self.program,
+            pc: self.pc
+        }
+    }
+    /// Execute the next statement. Returns `Finished` once there's
+    /// nothing left to run, including when a top-level GTFO or
+    /// FOUND YR stops the program early.
+    pub fn step(&mut self) -> Result<StepResult> {
+        if self.pc == 0 {
+            // Pre-process function calls, like eval_all
+            for ast in &self.program {
+                if let AST::HowIzI(..) = *ast.unlocated() {
+                    self.scope.eval_ref(ast)?;
+                }
+            }
+        }
+        let ast = match self.program.get(self.pc) {
+            Some(ast) => ast,
+            None => return Ok(StepResult::Finished)
+        };
+        self.pc += 1;
+        let result = self.scope.eval_ref(ast)?;
+        if let Return::None = result {
+            if self.pc < self.program.len() {
+                return Ok(StepResult::Continue);
+            }
+        }
+        self.pc = self.program.len();
+        Ok(StepResult::Finished)
+    }
+    /// Step until the program is finished
+    pub fn run(&mut self) -> Result<()> {
+        while self.step()? == StepResult::Continue {}
+        Ok(())
+    }
+    /// The index of the next statement to execute
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+    /// The line and column of the statement being executed. After `step`
+    /// fails, this is the innermost statement that failed. Only programs
+    /// from `parser::parse_located` have positions.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        self.scope.params().borrow().position
+    }
+    /// The top-level variables
+    pub fn variables(&self) -> Ref<'_, HashMap<String, Value>> {
+        self.scope.vars.borrow()
+    }
+    /// The top-level scope, e.g. for reading IT
+    pub fn scope(&self) -> &Scope<'a, R, W, E, T> {
+        &self.scope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser;
+    use tokenizer;
+
+    fn parse(code: &str) -> Vec<AST> {
+        parser::parse(tokenizer::tokenize_str(code).unwrap()).unwrap()
+    }
+    fn parse_located(code: &str) -> Vec<AST> {
+        parser::parse_located(tokenizer::tokenize_located(code).unwrap(), parser::Options::default()).unwrap()
+    }
+    fn run(code: &str) -> Result<String> {
+        let mut output = Vec::new();
+        EvalParams::new(io::empty(), &mut output).scope().eval_all(parse(code))?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+    fn eval(code: &str) -> Result<Value> {
+        match parse(code).pop() {
+            Some(AST::It(expr)) => EvalParams::new(io::empty(), io::sink()).scope().eval_expr(&expr),
+            ast => panic!("not an expression: {:?}", ast)
+        }
+    }
+
+    #[test]
+    fn comparison_types() {
+        for &(code, expected) in &[
+            ("BOTH SAEM 3 AN 3.0", true),
+            ("BOTH SAEM 3 AN \"3\"", false),
+            ("BOTH SAEM \"3\" AN \"3\"", true),
+            ("BOTH SAEM WIN AN 1", false),
+            ("DIFFRINT 3 AN 3.5", true),
+            ("DIFFRINT 3 AN \"3\"", true),
+            ("DIFFRINT FAIL AN \"\"", true)
+        ] {
+            assert_eq!(eval(code).unwrap(), Value::Troof(expected), "{}", code);
+        }
+        // Casting first makes them comparable
+        assert_eq!(eval("BOTH SAEM 3 AN MAEK \"3\" A NUMBR").unwrap(), Value::Troof(true));
+        // A SMOOSH can be an operand if it ends with MKAY
+        assert_eq!(eval("BOTH SAEM SMOOSH \"a\" AN \"b\" MKAY AN \"ab\"").unwrap(), Value::Troof(true));
+        assert_eq!(eval("DIFFRINT \"ab\" AN SMOOSH \"a\" AN \"b\" MKAY").unwrap(), Value::Troof(false));
+        assert_eq!(eval("SUM OF SMOOSH 1 AN 2 MKAY AN 3").unwrap(), Value::Numbr(15));
+        // NOT negates the whole comparison
+        assert_eq!(eval("NOT DIFFRINT 1 AN 1").unwrap(), Value::Troof(true));
+        assert_eq!(eval("NOT BOTH SAEM 1 AN 1").unwrap(), Value::Troof(false));
+        assert_eq!(eval("NOT BOTH SAEM 0 AN 1").unwrap(), Value::Troof(true));
+    }
+    #[test]
+    fn bare_comparison() {
+        assert_eq!(
+            run("\
+DIFFRINT 1 AN 2
+O RLY?
+    YA RLY, VISIBLE \"different\"
+    NO WAI, VISIBLE \"same\"
+OIC
+BOTH SAEM 1 AN 2
+O RLY?
+    YA RLY, VISIBLE \"same\"
+    NO WAI, VISIBLE \"different\"
+OIC").unwrap(),
+            "different\ndifferent\n"
+        );
+    }
+    #[test]
+    fn yarn_division() {
+        assert_eq!(eval("QUOSHUNT OF \"7\" AN \"2\"").unwrap(), Value::Numbr(3));
+        assert_eq!(eval("MOD OF \"7\" AN 2").unwrap(), Value::Numbr(1));
+        assert_eq!(eval("QUOSHUNT OF \"7\" AN 2.0").unwrap(), Value::Numbar(3.5));
+        assert_eq!(eval("MOD OF \"7.5\" AN \"2\"").unwrap(), Value::Numbar(1.5));
+    }
+    #[test]
+    fn nested_math() {
+        assert_eq!(eval("SUM OF PRODUKT OF 2 AN 3 AN 4").unwrap(), Value::Numbr(10));
+        assert_eq!(eval("DIFF OF 10 AN DIFF OF 4 AN 1").unwrap(), Value::Numbr(7));
+        assert_eq!(
+            eval("DIFF OF SUM OF PRODUKT OF 2 AN 3 AN 4 AN QUOSHUNT OF 8 AN 2").unwrap(),
+            Value::Numbr(6)
+        );
+        // Operands are evaluated left to right, so the error is for the first one
+        assert_eq!(
+            eval("SUM OF QUOSHUNT OF 1 AN 0 AN PRODUKT OF \"a\" AN 1").unwrap_err().to_string(),
+            "attempt to divide by zero"
+        );
+    }
+    #[test]
+    fn promotion() {
+        assert_eq!(eval("SUM OF 1 AN 2").unwrap(), Value::Numbr(3));
+        assert_eq!(eval("SUM OF 1 AN 2.0").unwrap(), Value::Numbar(3.0));
+        assert_eq!(eval("DIFF OF 1.5 AN 1").unwrap(), Value::Numbar(0.5));
+        assert_eq!(eval("PRODUKT OF 2 AN \"1.5\"").unwrap(), Value::Numbar(3.0));
+        assert_eq!(eval("BIGGR OF 1 AN 2.5").unwrap(), Value::Numbar(2.5));
+        assert_eq!(eval("SMALLR OF 1 AN 2.5").unwrap(), Value::Numbar(1.0));
+        assert_eq!(eval("BIGGR OF 3 AN 2").unwrap(), Value::Numbr(3));
+        assert_eq!(eval("BIGGR OF 3 AN 3.0").unwrap(), Value::Numbar(3.0));
+        assert_eq!(eval("SMALLR OF 3.0 AN 3").unwrap(), Value::Numbar(3.0));
+        assert_eq!(eval("BIGGR OF \"-1\" AN -1.5").unwrap(), Value::Numbar(-1.0));
+        assert_eq!(eval("SMALLR OF 2 AN 1.5").unwrap(), Value::Numbar(1.5));
+        assert_eq!(eval("SMALLR OF \"7\" AN 10").unwrap(), Value::Numbr(7));
+        assert_eq!(eval("BIGGR OF QUOSHUNT OF 0.0 AN 0.0 AN 2").unwrap(), Value::Numbar(2.0));
+        assert!(eval("BIGGR OF \"cat\" AN 2").is_err());
+        // Negative YARNs are still NUMBRs
+        assert_eq!(eval("SUM OF \"-5\" AN 1").unwrap(), Value::Numbr(-4));
+    }
+    #[test]
+    fn maek() {
+        for &(code, expected) in &[
+            ("MAEK 0 A TROOF", false),
+            ("MAEK 5 A TROOF", true),
+            ("MAEK 0.0 A TROOF", false),
+            ("MAEK \"\" A TROOF", false),
+            ("MAEK \"x\" A TROOF", true),
+            ("MAEK \"0\" TROOF", true)
+        ] {
+            assert_eq!(eval(code).unwrap(), Value::Troof(expected), "{}", code);
+        }
+        assert_eq!(eval("MAEK \"12\" A NUMBR").unwrap(), Value::Numbr(12));
+        assert_eq!(eval("MAEK 2 A NUMBAR").unwrap(), Value::Numbar(2.0));
+        assert_eq!(eval("MAEK WIN A YARN").unwrap(), Value::Yarn("WIN".to_string()));
+        assert_eq!(eval("MAEK \"42\" A NUMBR").unwrap(), Value::Numbr(42));
+        assert_eq!(eval("MAEK \"4.5\" A NUMBAR").unwrap(), Value::Numbar(4.5));
+        for (code, from, to) in &[
+            ("MAEK 1 A BUKKIT", Type::Numbr, Type::Bukkit),
+            ("MAEK \"abc\" A NUMBR", Type::Yarn, Type::Numbr),
+            ("MAEK \"4.5\" A NUMBR", Type::Yarn, Type::Numbr),
+            ("MAEK \"abc\" A NUMBAR", Type::Yarn, Type::Numbar)
+        ] {
+            match eval(code) {
+                Err(Error::BadCast { from: ref got_from, to: ref got_to }) if got_from == from && got_to == to => (),
+                result => panic!("unexpected result for {}: {:?}", code, result.map_err(|err| err.to_string()))
+            }
+        }
+        assert_eq!(eval("MAEK \"abc\" A NUMBR").unwrap_err().to_string(), "cannot cast YARN to NUMBR");
+    }
+    #[test]
+    fn variadic_troofs() {
+        assert_eq!(eval("ALL OF WIN AN WIN MKAY").unwrap(), Value::Troof(true));
+        assert_eq!(eval("ALL OF WIN AN 0 AN WIN").unwrap(), Value::Troof(false));
+        assert_eq!(eval("ANY OF FAIL AN \"\" AN 1 MKAY").unwrap(), Value::Troof(true));
+        assert_eq!(eval("ANY OF FAIL AN 0").unwrap(), Value::Troof(false));
+        // Operands of any type are cast to TROOF
+        assert_eq!(eval("ALL OF 1 AN 2 AN \"x\" MKAY").unwrap(), Value::Troof(true));
+        assert_eq!(eval("ALL OF 1.5 AN \"x\" AN \"\" MKAY").unwrap(), Value::Troof(false));
+        assert_eq!(eval("ANY OF 0 AN 0.0 AN \"\" AN FAIL MKAY").unwrap(), Value::Troof(false));
+        assert_eq!(eval("ANY OF 0 AN \"0\" MKAY").unwrap(), Value::Troof(true));
+        // Both stop at the first operand deciding the result
+        assert_eq!(eval("ALL OF FAIL AN I IZ UNDEFINED MKAY MKAY").unwrap(), Value::Troof(false));
+        assert_eq!(eval("ANY OF WIN AN I IZ UNDEFINED MKAY MKAY").unwrap(), Value::Troof(true));
+    }
+    #[test]
+    fn divide_by_zero() {
+        for code in &["QUOSHUNT OF 5 AN 0", "MOD OF 5 AN 0", "QUOSHUNT OF \"5\" AN \"0\""] {
+            match eval(code) {
+                Err(Error::DivideByZero) => (),
+                result => panic!("unexpected result for {}: {:?}", code, result.map_err(|err| err.to_string()))
+            }
+        }
+        // A NUMBAR on either side makes it IEEE division
+        assert_eq!(eval("QUOSHUNT OF 5.0 AN 0.0").unwrap(), Value::Numbar(f64::INFINITY));
+        assert_eq!(eval("QUOSHUNT OF 5 AN 0.0").unwrap(), Value::Numbar(f64::INFINITY));
+        match eval("MOD OF 5.0 AN 0") {
+            Ok(Value::Numbar(n)) if n.is_nan() => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn orly_on_any_expression() {
+        assert_eq!(
+            run("\
+HOW IZ I ISEVEN YR N
+    FOUND YR BOTH SAEM MOD OF N AN 2 AN 0
+IF U SAY SO
+IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 3
+    I IZ ISEVEN YR I MKAY, O RLY?
+        YA RLY, VISIBLE I \" even\"
+        NO WAI, VISIBLE I \" odd\"
+    OIC
+IM OUTTA YR LOOP
+WON OF WIN AN FAIL, O RLY?
+    YA RLY, VISIBLE \"won\"
+OIC
+\"\", O RLY?
+    YA RLY, VISIBLE \"not printed\"
+OIC").unwrap(),
+            "0 even\n1 odd\n2 even\nwon\n"
+        );
+    }
+    #[test]
+    fn loop_steps() {
+        let code = |op: &str, body: &str| format!("IM IN YR LOOP {} YR I
+    VISIBLE I \" \"!
+    BOTH SAEM I AN 3, O RLY?, YA RLY, GTFO, OIC
+    BOTH SAEM I AN -3, O RLY?, YA RLY, GTFO, OIC
+    {}
+IM OUTTA YR LOOP", op, body);
+        assert_eq!(run(&code("UPPIN", "")).unwrap(), "0 1 2 3 ");
+        assert_eq!(run(&code("NERFIN", "")).unwrap(), "0 -1 -2 -3 ");
+        // Whatever the body leaves in the variable is cast to a NUMBR
+        assert_eq!(run(&code("UPPIN", "I R MAEK I A YARN")).unwrap(), "0 1 2 3 ");
+        match run(&code("UPPIN", "I R \"abc\"")) {
+            Err(Error::InvalidCastLoop) => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        match run("IM IN YR LOOP UPPIN YR I\n    I R 9223372036854775807\nIM OUTTA YR LOOP") {
+            Err(Error::ArithmeticOverflow) => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn loop_guards() {
+        let count = "VISIBLE I!\nIM OUTTA YR LOOP";
+        assert_eq!(run(&format!("IM IN YR LOOP UPPIN YR I WILE DIFFRINT I AN 5\n{}", count)).unwrap(), "01234");
+        assert_eq!(run(&format!("IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 5\n{}", count)).unwrap(), "01234");
+        // Any value works as a guard, cast to a TROOF
+        assert_eq!(run(&format!("IM IN YR LOOP UPPIN YR I TIL DIFF OF 5 AN I\n{}", count)).unwrap(), "");
+        assert_eq!(run(&format!("IM IN YR LOOP UPPIN YR I WILE DIFF OF 5 AN I\n{}", count)).unwrap(), "01234");
+        assert_eq!(
+            run("IM IN YR LOOP UPPIN YR I\n    BOTH SAEM I AN 5, O RLY?, YA RLY, GTFO, OIC\n    VISIBLE I!\nIM OUTTA YR LOOP").unwrap(),
+            "01234"
+        );
+    }
+    #[test]
+    fn loop_order() {
+        assert_eq!(
+            run("IM IN YR LOOP UPPIN YR I WILE DIFFRINT I AN 3\nVISIBLE I\nIM OUTTA YR LOOP").unwrap(),
+            "0\n1\n2\n"
+        );
+        assert_eq!(
+            run("IM IN YR LOOP NERFIN YR I TIL BOTH SAEM I AN -2\nVISIBLE I\nIM OUTTA YR LOOP").unwrap(),
+            "0\n-1\n"
+        );
+        // A guard that's false from the start never runs the body
+        assert_eq!(run("IM IN YR LOOP UPPIN YR I WILE SMALLR OF I AN 3\nVISIBLE I\nIM OUTTA YR LOOP").unwrap(), "");
+        assert_eq!(run("IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 0\nVISIBLE I\nIM OUTTA YR LOOP").unwrap(), "");
+        // The body sees the variable before UPPIN is applied
+        assert_eq!(
+            run("\
+IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 6
+    VISIBLE I
+    I R SUM OF I AN 1
+IM OUTTA YR LOOP").unwrap(),
+            "0\n2\n4\n"
+        );
+    }
+    #[test]
+    fn recursion_limit() {
+        let code = "\
+HOW IZ I DEPTH YR N
+    BOTH SAEM N AN 0, O RLY?
+        YA RLY, FOUND YR 0
+    OIC
+    FOUND YR SUM OF 1 AN I IZ DEPTH YR DIFF OF N AN 1 MKAY
+IF U SAY SO";
+        let mut eval = EvalParams::new(io::empty(), io::sink());
+        eval.set_recursion_limit(10);
+        let scope = eval.scope();
+        scope.eval_all(parse(code)).unwrap();
+        // The outermost call counts too
+        assert_eq!(scope.call_func("DEPTH", vec![Value::Numbr(9)]).unwrap(), Value::Numbr(9));
+        match scope.call_func("DEPTH", vec![Value::Numbr(10)]) {
+            Err(Error::RecursionLimit(10)) => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        // Failed calls don't count against later ones
+        for _ in 0..20 {
+            assert!(scope.call_func("NOPE", Vec::new()).is_err());
+        }
+        assert_eq!(scope.call_func("DEPTH", vec![Value::Numbr(9)]).unwrap(), Value::Numbr(9));
+    }
+    #[test]
+    fn wtf_matching() {
+        let code = |it: &str| format!("{}, WTF?
+    OMG 3.0, VISIBLE \"three\", GTFO
+    OMG \"4\", VISIBLE \"four\", GTFO
+    OMGWTF, VISIBLE \"other\"
+OIC", it);
+        assert_eq!(run(&code("3")).unwrap(), "three\n");
+        assert_eq!(run(&code("3.0")).unwrap(), "three\n");
+        assert_eq!(run(&code("\"3\"")).unwrap(), "other\n");
+        assert_eq!(run(&code("\"4\"")).unwrap(), "four\n");
+        assert_eq!(run(&code("4")).unwrap(), "other\n");
+    }
+    #[test]
+    fn gtfo() {
+        // Breaks out of loops
+        assert_eq!(
+            run("IM IN YR LOOP UPPIN YR I\n    BOTH SAEM I AN 2, O RLY?, YA RLY, GTFO, OIC\n    VISIBLE I\nIM OUTTA YR LOOP").unwrap(),
+            "0\n1\n"
+        );
+        // Returns NOOB from functions
+        assert_eq!(
+            run("HOW IZ I FUNC\n    GTFO\n    FOUND YR 1\nIF U SAY SO\nVISIBLE MAEK I IZ FUNC MKAY A YARN").unwrap_err().to_string(),
+            "cannot cast NOOB to YARN"
+        );
+        // Stops falling through cases, without leaving the loop around it
+        assert_eq!(
+            run("\
+IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 4
+    I, WTF?
+        OMG 0, VISIBLE \"zero\"
+        OMG 1, VISIBLE \"one\", GTFO
+        OMG 2, VISIBLE \"two\"
+        OMGWTF, VISIBLE \"default\", GTFO
+    OIC
+IM OUTTA YR LOOP").unwrap(),
+            "zero\none\none\ntwo\ndefault\ndefault\n"
+        );
+    }
+    #[test]
+    fn function_scopes() {
+        assert_eq!(
+            run("\
+I HAS A X ITZ \"global\"
+HOW IZ I FUNC YR X
+    I HAS A TMP ITZ SUM OF X AN 1
+    FOUND YR TMP
+IF U SAY SO
+VISIBLE I IZ FUNC YR 41 MKAY
+VISIBLE X").unwrap(),
+            "42\nglobal\n"
+        );
+        match run("HOW IZ I FUNC\n    I HAS A TMP ITZ 1\nIF U SAY SO\nI IZ FUNC MKAY\nVISIBLE TMP") {
+            Err(Error::UndefinedVar(ref var)) if var == "TMP" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        match run("HOW IZ I FUNC YR ARG\nIF U SAY SO\nI IZ FUNC YR 1 MKAY\nVISIBLE ARG") {
+            Err(Error::UndefinedVar(ref var)) if var == "ARG" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        // Loops can assign outer variables, but their counter is their own
+        assert_eq!(
+            run("\
+I HAS A TOTAL ITZ 0
+IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 3
+    TOTAL R SUM OF TOTAL AN I
+IM OUTTA YR LOOP
+VISIBLE TOTAL").unwrap(),
+            "3\n"
+        );
+        match run("IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 3\nIM OUTTA YR LOOP\nVISIBLE I") {
+            Err(Error::UndefinedVar(ref var)) if var == "I" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn implicit_it() {
+        assert_eq!(
+            run("\
+BOTH SAEM 1 AN 1
+O RLY?
+    YA RLY, VISIBLE IT
+OIC
+SUM OF 1 AN 2
+VISIBLE IT").unwrap(),
+            "WIN\n3\n"
+        );
+        assert_eq!(run("SUM OF 2 AN 3\nVISIBLE IT").unwrap(), "5\n");
+        assert_eq!(eval("IT").unwrap(), Value::Noob);
+        // Statements that aren't expressions leave IT alone
+        assert_eq!(run("\"kept\", I HAS A X ITZ 1, X R 2, VISIBLE IT").unwrap(), "kept\n");
+    }
+    #[test]
+    fn it_scoping() {
+        assert_eq!(
+            run("\
+HOW IZ I FUNC
+    VISIBLE IT
+    \"inner\"
+IF U SAY SO
+\"outer\"
+VISIBLE I IZ FUNC MKAY
+VISIBLE IT
+WIN, O RLY?
+    YA RLY, \"block\"
+OIC
+VISIBLE IT").unwrap(),
+            "\ninner\nouter\nblock\n"
+        );
+    }
+    #[test]
+    fn injected_io() {
+        let mut output = Vec::new();
+        EvalParams::new(io::Cursor::new("hello\n"), &mut output)
+            .scope()
+            .eval_all(parse("VISIBLE \"hi\"\nGIMMEH LINE\nVISIBLE LINE"))
+            .unwrap();
+        assert_eq!(output, b"hi\nhello\n");
+    }
+    #[test]
+    fn typed_gimmeh() {
+        fn run_with(code: &str, input: &'static str) -> Result<String> {
+            let mut output = Vec::new();
+            EvalParams::new(io::Cursor::new(input), &mut output).scope().eval_all(parse(code))?;
+            Ok(String::from_utf8(output).unwrap())
+        }
+        let code = "I HAS A X ITZ A NUMBR, I HAS A Y ITZ A NUMBAR, I HAS A Z\n\
+                    GIMMEH X, GIMMEH Y, GIMMEH Z\n\
+                    VISIBLE SUM OF X AN 1 \" \" Y \" \" Z";
+        assert_eq!(run_with(code, "41\n1.5\n007\n").unwrap(), "42 1.50 007\n");
+        assert_eq!(run_with("I HAS A X ITZ 0.0, GIMMEH X, VISIBLE X", "3\n").unwrap(), "3.00\n");
+        match run_with(code, "forty-one\n1.5\n007\n") {
+            Err(Error::BadCast { from: Type::Yarn, to: Type::Numbr }) => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        // Declared in an outer scope, read in an inner one
+        assert_eq!(
+            run_with("I HAS A X ITZ 1, WIN, O RLY?, YA RLY, GIMMEH X, OIC, VISIBLE PRODUKT OF X AN 2", "21\n").unwrap(),
+            "42\n"
+        );
+    }
+    #[test]
+    fn stepping() {
+        let mut output = Vec::new();
+        {
+            let program = parse("I HAS A X ITZ 1\nX R SUM OF X AN 1\nVISIBLE X");
+            let mut interpreter = Interpreter::new(EvalParams::new(io::empty(), &mut output), program);
+            assert_eq!(interpreter.step().unwrap(), StepResult::Continue);
+            assert_eq!(interpreter.variables().get("X"), Some(&Value::Numbr(1)));
+            assert_eq!(interpreter.pc(), 1);
+            assert_eq!(interpreter.step().unwrap(), StepResult::Continue);
+            assert_eq!(interpreter.variables().get("X"), Some(&Value::Numbr(2)));
+            assert_eq!(interpreter.step().unwrap(), StepResult::Finished);
+            assert_eq!(interpreter.step().unwrap(), StepResult::Finished);
+            assert_eq!(interpreter.pc(), 3);
+        }
+        assert_eq!(output, b"2\n");
+
+        // Functions can be called before they're defined, and GTFO stops early
+        let program = parse("I IZ TWICE YR 2 MKAY\nGTFO\nVISIBLE \"unreachable\"\n\
+                             HOW IZ I TWICE YR X, FOUND YR PRODUKT OF X AN 2, IF U SAY SO");
+        let mut interpreter = Interpreter::new(EvalParams::new(io::empty(), io::sink()), program);
+        assert_eq!(interpreter.step().unwrap(), StepResult::Continue);
+        assert_eq!(*interpreter.scope().it().borrow(), Value::Numbr(4));
+        assert_eq!(interpreter.step().unwrap(), StepResult::Finished);
+
+        let mut interpreter = Interpreter::new(EvalParams::new(io::empty(), io::sink()), Vec::new());
+        assert_eq!(interpreter.step().unwrap(), StepResult::Finished);
+    }
+    #[test]
+    fn positions() {
+        // Functions are hoisted even with positions
+        let program = parse_located("VISIBLE 1\nI IZ DIVIDE YR 2 MKAY\n\
+                                     HOW IZ I DIVIDE YR X\n\
+                                     \x20 IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 2\n\
+                                     \x20   VISIBLE QUOSHUNT OF X AN DIFF OF 1 AN I\n\
+                                     \x20 IM OUTTA YR LOOP\n\
+                                     IF U SAY SO");
+        let mut output = Vec::new();
+        {
+            let mut interpreter = Interpreter::new(EvalParams::new(io::empty(), &mut output), program);
+            assert_eq!(interpreter.step().unwrap(), StepResult::Continue);
+            assert_eq!(interpreter.position(), None);
+            match interpreter.step() {
+                Err(Error::DivideByZero) => (),
+                result => panic!("unexpected result: {:?}", result)
+            }
+            assert_eq!(interpreter.position(), Some((5, 5)));
+        }
+        assert_eq!(output, b"1\n2\n");
+
+        let mut interpreter = Interpreter::new(EvalParams::new(io::empty(), io::sink()), parse("VISIBLE 1"));
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.position(), None);
+    }
+    #[test]
+    fn tracing() {
+        fn trace(program: Vec<AST>) -> (Result<()>, String) {
+            let mut trace = Vec::new();
+            let result = Interpreter::new(EvalParams::new(io::empty(), io::sink()), program)
+                .with_trace(&mut trace)
+                .run();
+            (result, String::from_utf8(trace).unwrap())
+        }
+        let (result, output) = trace(parse_located("I HAS A X ITZ 2\nSUM OF X AN 1\n\
+                                                   IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 2\n\
+                                                   \x20 I IZ SHOUT YR I MKAY\n\
+                                                   IM OUTTA YR LOOP\n\
+                                                   HOW IZ I SHOUT YR Y, VISIBLE Y, IF U SAY SO"));
+        result.unwrap();
+        assert_eq!(
+            output,
+            "1: I HAS A X ITZ 2 => IT = NOOB\n\
+             2: SUM OF X AN 1 => IT = NUMBR(3)\n\
+             3: IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 2\n\
+             4: I IZ SHOUT YR I MKAY\n\
+             6: VISIBLE Y => IT = NOOB\n\
+             4: ... => IT = NOOB\n\
+             4: I IZ SHOUT YR I MKAY\n\
+             6: VISIBLE Y => IT = NOOB\n\
+             4: ... => IT = NOOB\n\
+             3: ... => IT = NOOB\n"
+        );
+
+        // Failing statements are traced too, along with everything around them
+        let (result, output) = trace(parse_located("WIN, O RLY?\nYA RLY, VISIBLE QUOSHUNT OF 1 AN 0\nOIC"));
+        assert!(result.is_err());
+        assert_eq!(
+            output,
+            "1: WIN => IT = TROOF(WIN)\n\
+             1: O RLY?\n\
+             2: VISIBLE QUOSHUNT OF 1 AN 0 => error: attempt to divide by zero\n\
+             1: ... => error: attempt to divide by zero\n"
+        );
+
+        // Without positions, there are no line numbers
+        let (result, output) = trace(parse("VISIBLE \"hi\""));
+        result.unwrap();
+        assert_eq!(output, "VISIBLE \"hi\" => IT = NOOB\n");
+    }
+    #[test]
+    fn queued_input() {
+        let mut output = Vec::new();
+        {
+            let mut eval = EvalParams::new(io::Cursor::new("from stdin\n"), &mut output);
+            eval.queue_input(vec!["first".to_string(), "second".to_string()]);
+            eval.scope().eval_all(parse("I HAS A X, I HAS A Y, I HAS A Z\n\
+                                         GIMMEH X, GIMMEH Y, GIMMEH Z\n\
+                                         VISIBLE X \", \" Y \", \" Z")).unwrap();
+        }
+        assert_eq!(output, b"first, second, from stdin\n");
+    }
+    #[test]
+    fn io_policy() {
+        fn run_with(policy: IoPolicy, code: &str) -> Result<String> {
+            let mut output = Vec::new();
+            {
+                let mut eval = EvalParams::new(io::Cursor::new("hi\n"), &mut output).with_stderr(io::sink());
+                eval.set_io_policy(policy);
+                eval.scope().eval_all(parse(code))?;
+            }
+            Ok(String::from_utf8(output).unwrap())
+        }
+        let code = "GIMMEH X, VISIBLE X";
+        assert_eq!(run_with(IoPolicy::AllowAll, code).unwrap(), "hi\n");
+        for &(policy, code, statement) in &[
+            (IoPolicy::AllowNone, code, "GIMMEH"),
+            (IoPolicy::AllowNone, "VISIBLE 1", "VISIBLE"),
+            (IoPolicy::AllowNone, "INVISIBLE 1", "INVISIBLE"),
+            (IoPolicy::AllowOutput, code, "GIMMEH")
+        ] {
+            match run_with(policy, code) {
+                Err(Error::IoNotPermitted(got)) if got == statement => (),
+                result => panic!("unexpected result for {}: {:?}", code, result.map_err(|err| err.to_string()))
+            }
+        }
+        assert_eq!(run_with(IoPolicy::AllowOutput, "VISIBLE 1, INVISIBLE 2").unwrap(), "1\n");
+        assert_eq!(run_with(IoPolicy::AllowNone, "I HAS A X ITZ 1").unwrap(), "");
+    }
+    #[test]
+    fn captured() {
+        let scope = EvalParams::captured().scope();
+        scope.eval_all(parse("VISIBLE \"one\"")).unwrap();
+        assert_eq!(scope.params().borrow_mut().take_output(), "one\n");
+        scope.eval_all(parse("VISIBLE \"two\"!")).unwrap();
+        assert_eq!(scope.params().borrow_mut().take_output(), "two");
+        assert_eq!(scope.params().borrow_mut().take_output(), "");
+    }
+    #[test]
+    fn visible_exclamation() {
+        assert_eq!(run("I HAS A A ITZ 1, I HAS A B ITZ 2\nVISIBLE A AN B!\nVISIBLE A AN B !\nVISIBLE B!").unwrap(), "12122");
+    }
+    #[test]
+    fn invisible() {
+        let mut output = Vec::new();
+        let mut error = Vec::new();
+        EvalParams::new(io::empty(), &mut output)
+            .with_error_output(&mut error)
+            .scope()
+            .eval_all(parse("VISIBLE \"out\"\nINVISIBLE \"err\"\nINVISIBLE \"or\" \"s\"!"))
+            .unwrap();
+        assert_eq!(output, b"out\n");
+        assert_eq!(error, b"err\nors");
+    }
+    #[test]
+    fn working_scope() {
+        let mut output = Vec::new();
+        let mut params = EvalParams::new(io::empty(), &mut output);
+        let mut vars = HashMap::new();
+        vars.insert("X".to_string(), Value::Numbr(10));
+        params.set_working_scope(vars);
+
+        let scope = params.scope();
+        scope.eval_all(parse("VISIBLE SUM OF X AN 1\nX R 5")).unwrap();
+        assert_eq!(scope.find_var("X", |var| var.clone()), Some(Value::Numbr(5)));
+        drop(scope);
+        assert_eq!(output, b"11\n");
+    }
+    #[test]
+    fn slots() {
+        assert_eq!(
+            run("\
+I HAS A A ITZ A BUKKIT
+I HAS A B ITZ A BUKKIT
+B'Z C R 5
+A'Z B R B
+A'Z B'Z C R SUM OF A'Z B'Z C AN 1
+VISIBLE A'Z B'Z C \" \" B'Z C").unwrap(),
+            "6 5\n"
+        );
+        match run("I HAS A A ITZ A BUKKIT\nA'Z B R 1\nVISIBLE A'Z B'Z C") {
+            Err(Error::NotABukkit(ref key)) if key == "C" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        match run("I HAS A A ITZ 1\nA'Z B'Z C R 2") {
+            Err(Error::NotABukkit(ref key)) if key == "B" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+        match run("I HAS A A ITZ A BUKKIT\nVISIBLE A'Z B") {
+            Err(Error::UndefinedSlot(ref key)) if key == "B" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+
+        // Slots of anything else, which only a hand-built AST can have
+        let bukkit = Expr::Value(Value::Bukkit(vec![("X".to_string(), Value::Numbr(7))]));
+        let scope = EvalParams::new(io::empty(), io::sink()).scope();
+        assert_eq!(scope.eval_expr(&Expr::Slot(Box::new(bukkit), "X".to_string())).unwrap(), Value::Numbr(7));
+
+        // The parser only assigns to variables and slots, but the AST is public
+        let ast = AST::RSlot(Expr::Value(Value::Numbr(1)), "X".to_string(), Expr::Value(Value::Numbr(2)));
+        match EvalParams::new(io::empty(), io::sink()).scope().eval(ast) {
+            Err(Error::NotAPlace(Expr::Value(Value::Numbr(1)))) => (),
+            result => panic!("unexpected result: {:?}", result.map(|_| ()).map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn bukkit_equality() {
+        assert_eq!(
+            run("\
+I HAS A A ITZ A BUKKIT, A'Z X R 1, A'Z Y R \"y\"
+I HAS A B ITZ A BUKKIT, B'Z Y R \"y\", B'Z X R 1.0
+I HAS A C ITZ A BUKKIT, C'Z X R 1
+VISIBLE BOTH SAEM A AN A \" \" BOTH SAEM A AN B \" \" BOTH SAEM A AN C \" \" DIFFRINT A AN C
+C'Z Y R \"Y\"
+VISIBLE BOTH SAEM A AN C
+C'Z Y R \"y\"
+VISIBLE BOTH SAEM A AN C").unwrap(),
+            "WIN WIN FAIL WIN\nFAIL\nWIN\n"
+        );
+    }
+    #[test]
+    fn depth_limit() {
+        fn run_with(limit: Option<usize>, code: &str) -> Result<()> {
+            let mut eval = EvalParams::new(io::empty(), io::sink());
+            if let Some(limit) = limit {
+                eval.set_depth_limit(limit);
+            }
+            eval.scope().eval_all(parse(code)).map(|_| ())
+        }
+        let code = "\
+I HAS A EMPTY ITZ A BUKKIT, I HAS A B ITZ A BUKKIT, I HAS A N
+IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 20
+    N R EMPTY, N'Z INNER R B, B R N
+IM OUTTA YR LOOP
+VISIBLE BOTH SAEM B AN B";
+        run_with(None, code).unwrap();
+        run_with(Some(21), code).unwrap();
+        match run_with(Some(20), code) {
+            Err(Error::DepthLimit(20)) => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+
+        // Nesting through a slot counts the BUKKITs around it too
+        let code = "\
+I HAS A X ITZ A BUKKIT, X'Z A R X, I HAS A Y ITZ X
+X'Z A'Z B R 1, X'Z A'Z C R Y'Z A";
+        run_with(Some(3), code).unwrap();
+        match run_with(Some(3), &format!("{}, X'Z A'Z D R Y", code)) {
+            Err(Error::DepthLimit(3)) => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+
+        // Values from rust could be deeper than that, so comparing checks too
+        let deep = (0..10).fold(Value::Noob, |inner, _| Value::Bukkit(vec![("X".to_string(), inner)]));
+        let mut eval = EvalParams::new(io::empty(), io::sink());
+        eval.set_depth_limit(5);
+        eval.bind_func("DEEP", Some(0), move |_| deep.clone());
+        match eval.scope().eval_all(parse("BOTH SAEM I IZ DEEP MKAY AN I IZ DEEP MKAY")) {
+            Err(Error::DepthLimit(5)) => (),
+            result => panic!("unexpected result: {:?}", result.map(|_| ()).map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn return_bukkit() {
+        assert_eq!(
+            run("\
+HOW IZ I PAIR YR ONE AN YR TWO
+    I HAS A P ITZ A BUKKIT
+    P'Z ONE R ONE
+    P'Z TWO R TWO
+    FOUND YR P
+IF U SAY SO
+I HAS A P ITZ I IZ PAIR YR 1 AN YR \"b\" MKAY
+I HAS A Q ITZ I IZ PAIR YR P AN YR 3 MKAY
+P'Z ONE R 2
+VISIBLE P'Z ONE \" \" P'Z TWO \" \" Q'Z ONE'Z ONE \" \" Q'Z TWO").unwrap(),
+            "2 b 1 3\n"
+        );
+    }
+    #[test]
+    fn srs() {
+        assert_eq!(
+            run("\
+I HAS A X ITZ 1
+I HAS A NAME ITZ \"X\"
+SRS NAME R SUM OF SRS NAME AN 1
+VISIBLE SRS NAME \" \" X
+SRS \"NAME\" R \"Y\"
+VISIBLE NAME").unwrap(),
+            "2 2\nY\n"
+        );
+        match run("SRS \"NOPE\" R 1") {
+            Err(Error::UndefinedVar(ref name)) if name == "NOPE" => (),
+            result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn on_assign() {
+        fn assignments(code: &str) -> Vec<(String, Value)> {
+            let assigned = Rc::new(RefCell::new(Vec::new()));
+            let mut params = EvalParams::new(io::empty(), io::sink());
+            {
+                let assigned = Rc::clone(&assigned);
+                params.on_assign(move |name, value| assigned.borrow_mut().push((name.to_string(), value.clone())));
+            }
+            params.scope().eval_all(parse(code)).unwrap();
+            let assigned = assigned.borrow();
+            assigned.clone()
+        }
+        let assignment = |name: &str, value| (name.to_string(), value);
+        assert_eq!(assignments("I HAS A X\nX R 5\nSUM OF X AN 1"), &[
+            assignment("X", Value::Noob),
+            assignment("X", Value::Numbr(5)),
+            assignment("IT", Value::Numbr(6))
+        ]);
+        // Slots aren't variables
+        assert_eq!(assignments("I HAS A B ITZ A BUKKIT, B'Z X R 1, B'Z C R B, B'Z C'Z X R 2, I HAS A X ITZ 3"), &[
+            assignment("B", Value::Bukkit(Vec::new())),
+            assignment("B'Z X", Value::Numbr(1)),
+            assignment("B'Z C", Value::Bukkit(vec![("X".to_string(), Value::Numbr(1))])),
+            assignment("B'Z C'Z X", Value::Numbr(2)),
+            assignment("X", Value::Numbr(3))
+        ]);
+        // Loop counters, both when created and on every step
+        assert_eq!(assignments("IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 2, IM OUTTA YR LOOP"), &[
+            assignment("I", Value::Numbr(0)),
+            assignment("I", Value::Numbr(1)),
+            assignment("I", Value::Numbr(2))
+        ]);
+        // Function arguments
+        let code = "HOW IZ I ADD YR A AN YR B, FOUND YR SUM OF A AN B, IF U SAY SO\n\
+                    I HAS A X ITZ I IZ ADD YR 1 AN YR 2 MKAY";
+        assert_eq!(assignments(code), &[
+            assignment("A", Value::Numbr(1)),
+            assignment("B", Value::Numbr(2)),
+            assignment("X", Value::Numbr(3))
+        ]);
+    }
+    #[test]
+    fn overflow() {
+        for code in &[
+            "SUM OF 9223372036854775807 AN 1",
+            "DIFF OF -9223372036854775808 AN 1",
+            "PRODUKT OF 4611686018427387904 AN 2",
+            "QUOSHUNT OF -9223372036854775808 AN -1",
+            "MOD OF -9223372036854775808 AN -1"
+        ] {
+            match run(code) {
+                Err(Error::ArithmeticOverflow) => (),
+                result => panic!("unexpected result for {}: {:?}", code, result.map_err(|err| err.to_string()))
+            }
+        }
+        assert_eq!(run("VISIBLE SUM OF 9223372036854775806 AN 1").unwrap(), "9223372036854775807\n");
+        // NUMBARs just go to infinity
+        assert_eq!(run("VISIBLE PRODUKT OF 1e308 AN 10.0").unwrap(), "inf\n");
+    }
+    #[test]
+    fn arith_modes() {
+        fn run_with(mode: ArithMode, code: &str) -> String {
+            let mut eval = EvalParams::captured();
+            eval.set_arith_mode(mode);
+            let scope = eval.scope();
+            scope.eval_all(parse(code)).expect("Running test failed");
+            let output = scope.params().borrow_mut().take_output();
+            output
+        }
+        let code = "VISIBLE SUM OF 9223372036854775807 AN 1\n\
+                    VISIBLE DIFF OF -9223372036854775808 AN 1\n\
+                    VISIBLE PRODUKT OF 4611686018427387904 AN -3\n\
+                    VISIBLE QUOSHUNT OF -9223372036854775808 AN -1\n\
+                    VISIBLE MOD OF -9223372036854775808 AN -1";
+        assert_eq!(
+            run_with(ArithMode::Wrap, code),
+            "-9223372036854775808\n9223372036854775807\n4611686018427387904\n-9223372036854775808\n0\n"
+        );
+        assert_eq!(
+            run_with(ArithMode::Saturate, code),
+            "9223372036854775807\n-9223372036854775808\n-9223372036854775808\n9223372036854775807\n0\n"
+        );
+        assert_eq!(run_with(ArithMode::Checked, "VISIBLE SUM OF 9223372036854775806 AN 1"), "9223372036854775807\n");
+        let mut eval = EvalParams::new(io::empty(), io::sink());
+        eval.set_arith_mode(ArithMode::Saturate);
+        match eval.scope().eval_all(parse("VISIBLE QUOSHUNT OF 1 AN 0")) {
+            Err(Error::DivideByZero) => (),
+            result => panic!("unexpected result: {:?}", result.map(|_| ()).map_err(|err| err.to_string()))
+        }
+    }
+    #[test]
+    fn type_error() {
+        assert_eq!(
+            run("I HAS A X\nVISIBLE SUM OF 1 AN PRODUKT OF X AN \"hi\"").unwrap_err().to_string(),
+            "type error in PRODUKT OF X AN \"hi\""
+        );
+        assert_eq!(run("VISIBLE SUM OF \"abc\" AN 1").unwrap_err().to_string(), "type error in SUM OF \"abc\" AN 1");
+        assert_eq!(run("VISIBLE BIGGR OF 1 AN \"abc\"").unwrap_err().to_string(), "type error in BIGGR OF 1 AN \"abc\"");
+    }
+    #[test]
+    fn visible_operands() {
+        assert_eq!(
+            run("\
+                VISIBLE \"sum:: \" SUM OF 1 AN 2
+                VISIBLE \"a\" AN \"b\" \"c\"
+                I HAS A NOTHING
+                VISIBLE \"[\" NOTHING \"]\"\
+            ").unwrap(),
+            "sum: 3\nabc\n[]\n"
+        );
+    }
+}
@@ -1,11 +1,22 @@
+// failure_derive 0.1.8 (the latest) puts its impls inside an anonymous
+// const, which rustc now warns about. Only a crate-level allow reaches them.
+#![allow(non_local_definitions)]
+
 #[macro_use] extern crate failure;
 #[macro_use] extern crate unic_char_range;
 extern crate unic_ucd_name;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(feature = "serde")] extern crate serde_json;
+#[cfg(feature = "wasm")] extern crate wasm_bindgen;
 
+#[cfg(feature = "capi")] pub mod capi;
 pub mod eval;
 pub mod parser;
+pub mod rng;
+pub mod stdlib;
 pub mod tokenizer;
 pub mod types;
+#[cfg(feature = "wasm")] pub mod wasm;
 
 #[derive(Debug, Fail)]
 pub enum Error {
@@ -15,17 +26,22 @@ pub enum Error {
     ParseError(parser::Error),
     #[fail(display = "eval error: {}", _0)]
     EvalError(eval::Error),
+    #[fail(display = "io error: {}", _0)]
+    IoError(io::Error),
+    #[fail(display = "{}: {}", _0, _1)]
+    InFile(String, Box<Error>),
 }
 
-use eval::EvalParams;
+use eval::{EvalParams, Interpreter};
 use parser::AST;
-use std::io;
+use std::{fs, io, path::Path};
+use types::Value;
 
 /// Convenience function for tokenizing and parsing code
 pub fn parse(code: &str) -> Result<Vec<AST>, Error> {
-    let tokens = tokenizer::tokenize(code.chars()).map_err(|err| Error::TokenizeError(err))?;
+    let tokens = tokenizer::tokenize(code.chars()).map_err(Error::TokenizeError)?;
     #[cfg(feature = "debug")] println!("{:#?}", tokens);
-    let parsed = parser::parse(tokens).map_err(|err| Error::ParseError(err))?;
+    let parsed = parser::parse(tokens).map_err(Error::ParseError)?;
     #[cfg(feature = "debug")] println!("{:#?}", parsed);
     Ok(parsed)
 }
@@ -39,8 +55,47 @@ pub fn eval<R, W, F>(code: &str, stdin: R, stdout: W, callback: F) -> Result<(),
     let parsed = parse(code)?;
     let mut eval = eval::EvalParams::new(stdin, stdout);
     callback(&mut eval);
-    eval.scope().eval_all(parsed).map_err(|err| Error::EvalError(err))?;
-    Ok(())
+    Interpreter::new(eval, parsed).run().map_err(Error::EvalError)
+}
+
+/// Like `eval`, but reads the code from a file. Any error, including
+/// failing to read the file, is wrapped in `Error::InFile` with the path.
+pub fn eval_file<P, R, W, F>(path: P, stdin: R, stdout: W, callback: F) -> Result<(), Error>
+    where P: AsRef<Path>,
+          R: io::BufRead,
+          W: io::Write,
+          F: FnOnce(&mut EvalParams<R, W>)
+{
+    let path = path.as_ref();
+    fs::read_to_string(path)
+        .map_err(Error::IoError)
+        .and_then(|code| eval(&code, stdin, stdout, callback))
+        .map_err(|err| Error::InFile(path.display().to_string(), Box::new(err)))
+}
+
+/// Convenience function for running code using the process' stdin and stdout
+pub fn run_str(code: &str) -> Result<(), Error> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    eval(code, stdin.lock(), stdout.lock(), |_| ())
+}
+
+/// Like `run_str`, but returns the value of IT at the end of the program,
+/// so `SUM OF 2 AN 3` gives `Value::Numbr(5)`
+pub fn run_str_returning_it(code: &str) -> Result<Value, Error> {
+    let parsed = parse(code)?;
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut interpreter = Interpreter::new(EvalParams::new(stdin.lock(), stdout.lock()), parsed);
+    interpreter.run().map_err(Error::EvalError)?;
+    let it = interpreter.scope().it().borrow().clone();
+    Ok(it)
+}
+
+/// Convenience function for reading a file and running it with `run_str`
+pub fn run_file<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let code = fs::read_to_string(path).map_err(Error::IoError)?;
+    run_str(&code)
 }
 
 /// Convenience function for capturing the output of `eval`
@@ -53,21 +108,27 @@ pub fn capture<R, F>(code: &str, stdin: R, callback: F) -> Result<String, Error>
     Ok(String::from_utf8(output).expect("Program (somehow) returned non-utf8 data"))
 }
 
+/// Convenience function for running code without any input and
+/// returning everything it printed
+pub fn run_str_capture(code: &str) -> Result<String, Error> {
+    capture(code, io::empty(), |_| ())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use types::Value;
+    use std::{env, process};
 
     fn run(code: &str) -> Result<String, Error> {
-        capture(code, io::empty(), |_| ())
+        run_str_capture(code)
     }
 
     #[test]
     fn run_all() {
         assert_eq!(run(include_str!("../tests/fac.lol")).expect("Running test failed"), "120\n");
         assert_eq!(run(include_str!("../tests/implicit-return.lol")).expect("Running test failed"), "hi\n");
-        assert_eq!(run(include_str!("../tests/int-overflow.lol")).expect("Running test failed"), "WIN\n");
         assert_eq!(run(include_str!("../tests/pow.lol")).expect("Running test failed"), "32\n");
+        assert_eq!(run(include_str!("../tests/nan.lol")).expect("Running test failed"), "WIN\nWIN\n");
         assert_eq!(
             run(include_str!("../tests/function-ordering.lol")).expect("Running test failed"),
             "PING 5\n\
@@ -82,6 +143,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn empty_programs() {
+        for code in &["", "   \n  ", "BTW just a comment", "OBTW\nTLDR", "HAI 1.2\nKTHXBYE", "HAI 1.2\n\nKTHXBYE\n"] {
+            assert_eq!(run(code).expect("Running test failed"), "", "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn file_context() {
+        let path = env::temp_dir().join(format!("lci-file-context-{}.lol", process::id()));
+        fs::write(&path, "VISIBLE QUOSHUNT OF 1 AN 0").unwrap();
+        let result = eval_file(&path, io::empty(), io::sink(), |_| ());
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::InFile(ref file, ref err)) if *file == path.display().to_string() => match **err {
+                Error::EvalError(eval::Error::DivideByZero) => (),
+                _ => panic!("wrong error: {}", err)
+            },
+            _ => panic!("Running test failed (in a bad way)")
+        }
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with(&path.display().to_string()), "{}", err);
+
+        match eval_file("tests/does-not-exist.lol", io::empty(), io::sink(), |_| ()) {
+            Err(Error::InFile(file, err)) => {
+                assert_eq!(file, "tests/does-not-exist.lol");
+                match *err {
+                    Error::IoError(_) => (),
+                    _ => panic!("wrong error: {}", err)
+                }
+            },
+            _ => panic!("Running test failed (in a bad way)")
+        }
+    }
+
+    #[test]
+    fn returning_it() {
+        assert_eq!(run_str_returning_it("SUM OF 2 AN 3").unwrap(), Value::Numbr(5));
+        assert_eq!(run_str_returning_it("I HAS A X ITZ 2.5\nPRODUKT OF X AN 2\nI HAS A Y").unwrap(), Value::Numbar(5.0));
+        assert_eq!(run_str_returning_it("").unwrap(), Value::Noob);
+        match run_str_returning_it("QUOSHUNT OF 1 AN 0") {
+            Err(Error::EvalError(eval::Error::DivideByZero)) => (),
+            _ => panic!("Running test failed (in a bad way)")
+        }
+    }
+
+    #[test]
+    fn capture_newlines() {
+        assert_eq!(run_str_capture("VISIBLE \"a\"").unwrap(), "a\n");
+        assert_eq!(run_str_capture("VISIBLE \"a\"!").unwrap(), "a");
+        assert_eq!(run_str_capture("VISIBLE \"a\" \"b\"!").unwrap(), "ab");
+        assert_eq!(run_str_capture("VISIBLE \"a\"!\nVISIBLE \"b\"").unwrap(), "ab\n");
+        assert_eq!(run_str_capture("VISIBLE \"a:)b\"").unwrap(), "a\nb\n");
+        assert_eq!(run_str_capture("VISIBLE \"a:)\"!\nVISIBLE \":)b\"").unwrap(), "a\n\nb\n");
+        assert_eq!(run_str_capture("VISIBLE \"a:)\" \"b:)\"!").unwrap(), "a\nb\n");
+    }
+
     #[test]
     fn rust_callback() {
         assert_eq!(
@@ -100,6 +218,14 @@ mod tests {
             Err(Error::EvalError(eval::Error::DivideByZero)) => (),
             _ => panic!("Running test failed (in a bad way)")
         }
+        match run(include_str!("../tests/fail/int-overflow.lol")) {
+            Err(Error::EvalError(eval::Error::ArithmeticOverflow)) => (),
+            _ => panic!("Running test failed (in a bad way)")
+        }
+        match run(include_str!("../tests/fail/trailing-mkay.lol")) {
+            Err(Error::ParseError(parser::Error::UnexpectedMkay)) => (),
+            _ => panic!("Running test failed (in a bad way)")
+        }
         match run(include_str!("../tests/fail/stack-overflow.lol")) {
             Err(Error::EvalError(eval::Error::RecursionLimit(_))) => (),
             _ => panic!("Running test failed (in a bad way)")
@@ -1,9 +1,9 @@
 extern crate lci;
 
-use std::{env, fs, io};
+use std::{env, io};
 
 fn main() {
-    let file = match env::args().skip(1).next() {
+    let file = match env::args().nth(1) {
         Some(file) => file,
         None => {
             eprintln!("usage: lci <file>");
@@ -11,23 +11,9 @@ fn main() {
         }
     };
 
-    let input = match fs::read_to_string(file) {
-        Ok(input) => input,
-        Err(err) => {
-            eprintln!("error reading file: {}", err);
-            return;
-        }
-    };
-
     let stdin = io::stdin();
-    let stdin = stdin.lock();
     let stdout = io::stdout();
-    let stdout = stdout.lock();
-
-    match lci::eval(&input, stdin, stdout, |_| ()) {
-        Ok(()) => (),
-        Err(err) => {
-            eprintln!("{}", err);
-        }
+    if let Err(err) = lci::eval_file(file, stdin.lock(), stdout.lock(), |_| ()) {
+        eprintln!("{}", err);
     }
 }
@@ -1,9 +1,12 @@
 use std::{
+    cell::Cell,
+    fmt,
     iter::Peekable,
+    rc::Rc,
     result::Result as StdResult
 };
-use tokenizer::Token;
-use types::Value;
+use tokenizer::{Located, Token};
+use types::{Interpolate, Type, Value};
 
 #[derive(Debug, Fail)]
 pub enum Error {
@@ -13,10 +16,14 @@ pub enum Error {
     ExpectedToken(Token, Token),
     #[fail(display = "loop label mismatch. started {:?}, got {:?}", _0, _1)]
     LabelMismatch(String, String),
+    #[fail(display = "expected a line break or comma before {:?}", _0)]
+    MissingSeparator(Token),
     #[fail(display = "trailing characters after statement")]
     Trailing,
     #[fail(display = "unexpected end of file")]
-    UnexpectedEOF
+    UnexpectedEOF,
+    #[fail(display = "MKAY without anything to end")]
+    UnexpectedMkay
 }
 
 type Result<T> = StdResult<T, Error>;
@@ -33,6 +40,9 @@ pub enum Expr {
     Var(String),
     Value(Value),
     IIz(String, Vec<Expr>),
+    Srs(Box<Expr>),
+    Slot(Box<Expr>, String),
+    Maek(Box<Expr>, Type),
 
     SumOf(Box<Expr>, Box<Expr>),
     DiffOf(Box<Expr>, Box<Expr>),
@@ -58,6 +68,8 @@ pub enum Expr {
 pub enum AST {
     IHasA(String, Expr),
     R(String, Expr),
+    RSrs(Expr, Expr),
+    RSlot(Expr, String, Expr),
     It(Expr),
     ORly(Vec<AST>, Vec<(Expr, Vec<AST>)>, Vec<AST>),
     Wtf(Vec<(Expr, Vec<AST>)>, Vec<AST>),
@@ -68,19 +80,251 @@ pub enum AST {
     FoundYr(Expr),
 
     Visible(Vec<Expr>, bool),
+    Invisible(Vec<Expr>, bool),
     Gimmeh(String),
+
+    /// A statement along with where it starts, see `parse_located`
+    Located(Box<Located<AST>>)
+}
+impl AST {
+    /// The statement itself, without its position
+    pub fn unlocated(&self) -> &AST {
+        match *self {
+            AST::Located(ref located) => &located.inner,
+            ref ast => ast
+        }
+    }
+}
+
+fn fmt_yarn(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    for c in string.chars() {
+        match c {
+            '\n' => write!(f, ":)")?,
+            '\t' => write!(f, ":>")?,
+            '\x07' => write!(f, ":o")?,
+            '"' => write!(f, ":\"")?,
+            ':' => write!(f, "::")?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    Ok(())
+}
+fn fmt_value(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match *value {
+        Value::Noob => write!(f, "NOOB"),
+        Value::Yarn(ref string) => {
+            write!(f, "\"")?;
+            fmt_yarn(f, string)?;
+            write!(f, "\"")
+        },
+        Value::YarnRaw(ref parts) => {
+            write!(f, "\"")?;
+            for part in parts {
+                match *part {
+                    Interpolate::Str(ref string) => fmt_yarn(f, string)?,
+                    Interpolate::Var(ref var) => write!(f, ":{{{}}}", var)?
+                }
+            }
+            write!(f, "\"")
+        },
+        Value::Numbr(n) => write!(f, "{}", n),
+        // Debug formatting keeps the decimal point on whole numbers
+        Value::Numbar(n) => write!(f, "{:?}", n),
+        Value::Troof(true) => write!(f, "WIN"),
+        Value::Troof(false) => write!(f, "FAIL"),
+        Value::Bukkit(_) => write!(f, "<BUKKIT>")
+    }
+}
+fn fmt_multiple(f: &mut fmt::Formatter, exprs: &[Expr]) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, " AN ")?;
+        }
+        write!(f, "{}", expr)?;
+    }
+    write!(f, " MKAY")
+}
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::It => write!(f, "IT"),
+            Expr::Var(ref name) => write!(f, "{}", name),
+            Expr::Value(ref value) => fmt_value(f, value),
+            Expr::IIz(ref name, ref args) => {
+                write!(f, "I IZ {}", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{} YR {}", if i == 0 { "" } else { " AN" }, arg)?;
+                }
+                write!(f, " MKAY")
+            },
+            Expr::Srs(ref inner) => write!(f, "SRS {}", inner),
+            Expr::Slot(ref bukkit, ref key) => write!(f, "{}'Z {}", bukkit, key),
+            Expr::Maek(ref inner, kind) => write!(f, "MAEK {} A {}", inner, kind),
+
+            Expr::SumOf(ref one, ref two) => write!(f, "SUM OF {} AN {}", one, two),
+            Expr::DiffOf(ref one, ref two) => write!(f, "DIFF OF {} AN {}", one, two),
+            Expr::ProduktOf(ref one, ref two) => write!(f, "PRODUKT OF {} AN {}", one, two),
+            Expr::QuoshuntOf(ref one, ref two) => write!(f, "QUOSHUNT OF {} AN {}", one, two),
+            Expr::ModOf(ref one, ref two) => write!(f, "MOD OF {} AN {}", one, two),
+            Expr::BiggrOf(ref one, ref two) => write!(f, "BIGGR OF {} AN {}", one, two),
+            Expr::SmallrOf(ref one, ref two) => write!(f, "SMALLR OF {} AN {}", one, two),
+
+            Expr::BothOf(ref one, ref two) => write!(f, "BOTH OF {} AN {}", one, two),
+            Expr::EitherOf(ref one, ref two) => write!(f, "EITHER OF {} AN {}", one, two),
+            Expr::WonOf(ref one, ref two) => write!(f, "WON OF {} AN {}", one, two),
+            Expr::Not(ref inner) => write!(f, "NOT {}", inner),
+            Expr::AllOf(ref exprs) => {
+                write!(f, "ALL OF ")?;
+                fmt_multiple(f, exprs)
+            },
+            Expr::AnyOf(ref exprs) => {
+                write!(f, "ANY OF ")?;
+                fmt_multiple(f, exprs)
+            },
+
+            Expr::BothSaem(ref one, ref two) => write!(f, "BOTH SAEM {} AN {}", one, two),
+            Expr::Diffrint(ref one, ref two) => write!(f, "DIFFRINT {} AN {}", one, two),
+
+            Expr::Smoosh(ref exprs) => {
+                write!(f, "SMOOSH ")?;
+                fmt_multiple(f, exprs)
+            }
+        }
+    }
+}
+fn fmt_block(f: &mut fmt::Formatter, block: &[AST]) -> fmt::Result {
+    for ast in block {
+        for line in ast.to_string().lines() {
+            writeln!(f, "    {}", line)?;
+        }
+    }
+    Ok(())
+}
+fn fmt_visible(f: &mut fmt::Formatter, keyword: &str, exprs: &[Expr], newline: bool) -> fmt::Result {
+    write!(f, "{}", keyword)?;
+    for (i, expr) in exprs.iter().enumerate() {
+        write!(f, "{} {}", if i == 0 { "" } else { " AN" }, expr)?;
+    }
+    if !newline {
+        write!(f, "!")?;
+    }
+    Ok(())
+}
+/// Prints the statement as LOLCODE that parses back into the same AST.
+/// Loop labels aren't kept around, so all loops are called LOOP.
+impl fmt::Display for AST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AST::IHasA(ref ident, Expr::Value(Value::Noob)) => write!(f, "I HAS A {}", ident),
+            AST::IHasA(ref ident, Expr::Value(Value::Bukkit(ref slots))) if slots.is_empty() =>
+                write!(f, "I HAS A {} ITZ A BUKKIT", ident),
+            AST::IHasA(ref ident, ref expr) => write!(f, "I HAS A {} ITZ {}", ident, expr),
+            AST::R(ref ident, ref expr) => write!(f, "{} R {}", ident, expr),
+            AST::RSrs(ref name, ref expr) => write!(f, "SRS {} R {}", name, expr),
+            AST::RSlot(ref bukkit, ref key, ref expr) => write!(f, "{}'Z {} R {}", bukkit, key, expr),
+            AST::It(ref expr) => write!(f, "{}", expr),
+            AST::ORly(ref yarly, ref mebbe, ref nowai) => {
+                writeln!(f, "O RLY?")?;
+                writeln!(f, "YA RLY")?;
+                fmt_block(f, yarly)?;
+                for (condition, block) in mebbe {
+                    writeln!(f, "MEBBE {}", condition)?;
+                    fmt_block(f, block)?;
+                }
+                if !nowai.is_empty() {
+                    writeln!(f, "NO WAI")?;
+                    fmt_block(f, nowai)?;
+                }
+                write!(f, "OIC")
+            },
+            AST::Wtf(ref omg, ref omgwtf) => {
+                writeln!(f, "WTF?")?;
+                for (expr, block) in omg {
+                    writeln!(f, "OMG {}", expr)?;
+                    fmt_block(f, block)?;
+                }
+                if !omgwtf.is_empty() {
+                    writeln!(f, "OMGWTF")?;
+                    fmt_block(f, omgwtf)?;
+                }
+                write!(f, "OIC")
+            },
+            AST::ImInYr(ref operation, ref var, ref condition, ref block) => {
+                write!(f, "IM IN YR LOOP ")?;
+                match *operation {
+                    Operation::Uppin => write!(f, "UPPIN YR {}", var)?,
+                    Operation::Nerfin => write!(f, "NERFIN YR {}", var)?,
+                    Operation::IIz(ref name) => write!(f, "I IZ {} YR {} MKAY", name, var)?
+                }
+                // TIL is parsed as WILE NOT, so print it the way it's usually written
+                match *condition {
+                    Some(Expr::Not(ref condition)) => write!(f, " TIL {}", condition)?,
+                    Some(ref condition) => write!(f, " WILE {}", condition)?,
+                    None => ()
+                }
+                writeln!(f)?;
+                fmt_block(f, block)?;
+                write!(f, "IM OUTTA YR LOOP")
+            },
+            AST::HowIzI(ref name, ref args, ref block) => {
+                write!(f, "HOW IZ I {}", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{} YR {}", if i == 0 { "" } else { " AN" }, arg)?;
+                }
+                writeln!(f)?;
+                fmt_block(f, block)?;
+                write!(f, "IF U SAY SO")
+            },
+
+            AST::Gtfo => write!(f, "GTFO"),
+            AST::FoundYr(ref expr) => write!(f, "FOUND YR {}", expr),
+
+            AST::Visible(ref exprs, newline) => fmt_visible(f, "VISIBLE", exprs, newline),
+            AST::Invisible(ref exprs, newline) => fmt_visible(f, "INVISIBLE", exprs, newline),
+            AST::Gimmeh(ref ident) => write!(f, "GIMMEH {}", ident),
+
+            AST::Located(ref located) => write!(f, "{}", located.inner)
+        }
+    }
+}
+
+/// Options for tweaking the language accepted by the parser
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Don't require a separator between statements, as in
+    /// `BOTH SAEM X AN 1 O RLY?`
+    pub lenient: bool,
+    /// Accept `I HAS A X R 1`, declaring like `ITZ` does
+    pub declare_with_r: bool
+}
+
+/// Strips the positions off tokens, remembering the line, column and
+/// offset of the last one read
+struct Positions<I> {
+    iter: I,
+    last: Rc<Cell<(usize, usize, usize)>>
+}
+impl<I: Iterator<Item = Located<Token>>> Iterator for Positions<I> {
+    type Item = Token;
+    fn next(&mut self) -> Option<Token> {
+        let token = self.iter.next()?;
+        self.last.set((token.line, token.col, token.offset));
+        Some(token.inner)
+    }
 }
 
 pub struct Parser<I: Iterator<Item = Token>> {
-    pub iter: Peekable<I>
+    pub iter: Peekable<I>,
+    pub options: Options,
+    /// Where the last token read is, if the input had positions
+    positions: Option<Rc<Cell<(usize, usize, usize)>>>
 }
 impl<I: Iterator<Item = Token>> Parser<I> {
     fn block(&mut self, until: &[Token]) -> Result<Vec<AST>> {
         let mut block = Vec::new();
         loop {
-            match self.iter.peek() {
-                Some(token) => if until.contains(&token) { break; },
-                None => ()
+            if let Some(token) = self.iter.peek() {
+                if until.contains(token) { break; }
             }
             if let Some(ast) = self.statement()? {
                 block.push(ast);
@@ -103,7 +347,11 @@ impl<I: Iterator<Item = Token>> Parser<I> {
         }
     }
     fn expect_expr(&mut self) -> Result<Expr> {
-        self.expression()?.ok_or(Error::ExpectedKind("expression"))
+        match self.expression()? {
+            Some(expr) => Ok(expr),
+            None if self.iter.peek() == Some(&Token::Mkay) => Err(Error::UnexpectedMkay),
+            None => Err(Error::ExpectedKind("expression"))
+        }
     }
     fn expect_ident(&mut self) -> Result<String> {
         match self.iter.next() {
@@ -111,6 +359,19 @@ impl<I: Iterator<Item = Token>> Parser<I> {
             _ => Err(Error::ExpectedKind("identifier"))
         }
     }
+    /// Read any `'Z` slot accesses following `expr`
+    fn slots(&mut self, mut expr: Expr) -> Result<Expr> {
+        while let Some(&Token::ApostropheZ) = self.iter.peek() {
+            self.iter.next();
+            let key = match self.iter.next() {
+                Some(Token::Ident(key)) => key,
+                Some(Token::Value(Value::Numbr(n))) => n.to_string(),
+                _ => return Err(Error::ExpectedKind("slot name"))
+            };
+            expr = Expr::Slot(Box::new(expr), key);
+        }
+        Ok(expr)
+    }
     fn trim(&mut self) {
         while let Some(&Token::Separator) = self.iter.peek() {
             self.iter.next();
@@ -118,19 +379,47 @@ impl<I: Iterator<Item = Token>> Parser<I> {
     }
     /// Read one statement from the AST, including trailing line separator
     pub fn statement(&mut self) -> Result<Option<AST>> {
-        let stmt = self.inner_statement()?;
-        match self.iter.next() {
-            None | Some(Token::Separator) => Ok(stmt),
-            _ => Err(Error::Trailing)
+        // Peeking reads the first token, so that's the last position seen
+        self.iter.peek();
+        let start = self.positions.as_ref().map(|last| last.get());
+        let stmt = self.inner_statement()?.map(|ast| match start {
+            Some((line, col, offset)) => AST::Located(Box::new(Located { line, col, offset, inner: ast })),
+            None => ast
+        });
+        match self.iter.peek() {
+            None => Ok(stmt),
+            Some(&Token::Separator) => {
+                self.iter.next();
+                Ok(stmt)
+            },
+            // Anything that could use an MKAY would've consumed it
+            Some(&Token::Mkay) => Err(Error::UnexpectedMkay),
+            // Leave the token to start the next statement
+            Some(_) if self.options.lenient && stmt.is_some() => Ok(stmt),
+            Some(token) => Err(Error::MissingSeparator(token.clone()))
         }
     }
+    /// Read statements until the end of the input
+    fn program(&mut self) -> Result<Vec<AST>> {
+        let mut parsed = Vec::new();
+        while self.iter.peek().is_some() {
+            if let Some(ast) = self.statement()? {
+                parsed.push(ast);
+            }
+        }
+        Ok(parsed)
+    }
     fn inner_statement(&mut self) -> Result<Option<AST>> {
         match self.iter.peek() {
+            Some(&Token::Mkay) => Err(Error::UnexpectedMkay),
+            // HAI and KTHXBYE don't produce any statements, but like any
+            // other statement, they must be followed by a line break or a
+            // comma, even when lenient. Comments are fine.
             Some(&Token::Hai) => {
                 self.iter.next();
                 match self.iter.next() {
                     Some(Token::Value(_)) => Ok(None),
-                    _ => return Err(Error::ExpectedKind("numbar"))
+                    _ => Err(Error::ExpectedKind("numbar"))
                 }
             },
             Some(&Token::KThxBye) => {
@@ -151,9 +440,21 @@ impl<I: Iterator<Item = Token>> Parser<I> {
                 match self.iter.peek() {
                     Some(&Token::Itz) => {
                         self.iter.next();
+                        if let Some(&Token::A) = self.iter.peek() {
+                            self.iter.next();
+                            let kind = match self.iter.next() {
+                                Some(Token::Type(kind)) => kind,
+                                _ => return Err(Error::ExpectedKind("type"))
+                            };
+                            return Ok(Some(AST::IHasA(ident, Expr::Value(kind.default_value()))));
+                        }
                         let expression = self.expect_expr()?;
                         Ok(Some(AST::IHasA(ident, expression)))
                     },
+                    Some(&Token::R) if self.options.declare_with_r => {
+                        self.iter.next();
+                        Ok(Some(AST::IHasA(ident, self.expect_expr()?)))
+                    },
                     None | Some(&Token::Separator) => {
                         Ok(Some(AST::IHasA(ident, Expr::Value(Value::Noob))))
                     },
@@ -162,26 +463,46 @@ impl<I: Iterator<Item = Token>> Parser<I> {
             },
             Some(&Token::Ident(_)) => {
                 if let Some(Token::Ident(ident)) = self.iter.next() {
+                    let target = self.slots(Expr::Var(ident))?;
                     match self.iter.peek() {
                         Some(&Token::R) => {
                             self.iter.next();
                             let expression = self.expect_expr()?;
-                            Ok(Some(AST::R(ident, expression)))
+                            match target {
+                                Expr::Var(ident) => Ok(Some(AST::R(ident, expression))),
+                                Expr::Slot(bukkit, key) => Ok(Some(AST::RSlot(*bukkit, key, expression))),
+                                _ => unreachable!()
+                            }
                         },
                         None | Some(&Token::Separator) => {
-                            Ok(Some(AST::It(Expr::Var(ident))))
+                            Ok(Some(AST::It(target)))
                         },
                         _ => Err(Error::Trailing)
                     }
                 } else { unreachable!(); }
             },
+            Some(&Token::Srs) => {
+                self.iter.next();
+                let name = self.expect_expr()?;
+                match self.iter.peek() {
+                    Some(&Token::R) => {
+                        self.iter.next();
+                        let expression = self.expect_expr()?;
+                        Ok(Some(AST::RSrs(name, expression)))
+                    },
+                    None | Some(&Token::Separator) => {
+                        Ok(Some(AST::It(Expr::Srs(Box::new(name)))))
+                    },
+                    _ => Err(Error::Trailing)
+                }
+            },
             Some(&Token::ORly) => {
                 self.iter.next();
                 self.expect(Token::Separator)?;
                 self.trim();
                 self.expect(Token::YaRly)?;
                 self.expect(Token::Separator)?;
-                let mut yarly = self.block(&[Token::Mebbe, Token::NoWai, Token::Oic])?;
+                let yarly = self.block(&[Token::Mebbe, Token::NoWai, Token::Oic])?;
 
                 let mut mebbe = Vec::new();
                 self.trim();
@@ -189,7 +510,7 @@ impl<I: Iterator<Item = Token>> Parser<I> {
                     self.iter.next();
                     let condition = self.expect_expr()?;
                     self.expect(Token::Separator)?;
-                    let mut block = self.block(&[Token::Mebbe, Token::NoWai, Token::Oic])?;
+                    let block = self.block(&[Token::Mebbe, Token::NoWai, Token::Oic])?;
                     self.trim();
 
                     mebbe.push((condition, block));
@@ -214,12 +535,12 @@ impl<I: Iterator<Item = Token>> Parser<I> {
                     self.iter.next();
                     let expr = self.expect_expr()?;
                     self.expect(Token::Separator)?;
-                    let mut block = self.block(&[Token::Omg, Token::OmgWtf, Token::Oic])?;
+                    let block = self.block(&[Token::Omg, Token::OmgWtf, Token::Oic])?;
                     self.trim();
 
                     omg.push((expr, block));
                 }
-                let mut omgwtf = if let Some(&Token::OmgWtf) = self.iter.peek() {
+                let omgwtf = if let Some(&Token::OmgWtf) = self.iter.peek() {
                     self.iter.next();
                     self.expect(Token::Separator)?;
                     self.block(&[Token::Oic])?
@@ -275,25 +596,30 @@ impl<I: Iterator<Item = Token>> Parser<I> {
 
                 Ok(Some(AST::HowIzI(name, args, block)))
             },
-            Some(&Token::Visible) => {
-                self.iter.next();
+            Some(&Token::Visible) | Some(&Token::Invisible) => {
+                let visible = self.iter.next() == Some(Token::Visible);
                 let mut exprs = Vec::new();
                 let newline = loop {
                     exprs.push(self.expect_expr()?);
                     match self.iter.peek() {
                         Some(&Token::Exclamation) => { self.iter.next(); break false },
                         None | Some(&Token::Separator) => break true,
+                        Some(&Token::An) => { self.iter.next(); },
                         _ => ()
                     }
                 };
-                Ok(Some(AST::Visible(exprs, newline)))
+                if visible {
+                    Ok(Some(AST::Visible(exprs, newline)))
+                } else {
+                    Ok(Some(AST::Invisible(exprs, newline)))
+                }
             },
             Some(&Token::Gimmeh) => {
                 self.iter.next();
                 let ident = self.expect_ident()?;
                 Ok(Some(AST::Gimmeh(ident)))
             },
-            _ => Ok(self.expression()?.map(|expr| AST::It(expr)))
+            _ => Ok(self.expression()?.map(AST::It))
         }
     }
     fn two_exprs(&mut self) -> Result<(Box<Expr>, Box<Expr>)> {
@@ -342,9 +668,25 @@ impl<I: Iterator<Item = Token>> Parser<I> {
             },
             Some(&Token::Ident(_)) => {
                 if let Some(Token::Ident(var)) = self.iter.next() {
-                    Ok(Some(Expr::Var(var)))
+                    Ok(Some(self.slots(Expr::Var(var))?))
                 } else { unreachable!(); }
             },
+            Some(&Token::Srs) => {
+                self.iter.next();
+                Ok(Some(Expr::Srs(Box::new(self.expect_expr()?))))
+            },
+            Some(&Token::Maek) => {
+                self.iter.next();
+                let expr = self.expect_expr()?;
+                // The A is optional
+                if let Some(&Token::A) = self.iter.peek() {
+                    self.iter.next();
+                }
+                match self.iter.next() {
+                    Some(Token::Type(kind)) => Ok(Some(Expr::Maek(Box::new(expr), kind))),
+                    _ => Err(Error::ExpectedKind("type"))
+                }
+            },
             Some(&Token::IIz) => {
                 self.iter.next();
                 let name = self.expect_ident()?;
@@ -400,19 +742,43 @@ impl<I: Iterator<Item = Token>> Parser<I> {
 
 /// Convenience function for reading all AST from `input`
 pub fn parse<I: IntoIterator<Item = Token>>(input: I) -> Result<Vec<AST>> {
-    let mut parser = Parser { iter: input.into_iter().peekable() };
-    let mut parsed = Vec::new();
-    while parser.iter.peek().is_some() {
-        if let Some(ast) = parser.statement()? {
-            parsed.push(ast);
-        }
-    }
-    Ok(parsed)
+    parse_with(input, Options::default())
+}
+/// Convenience function for reading all AST from `input` with custom options
+pub fn parse_with<I: IntoIterator<Item = Token>>(input: I, options: Options) -> Result<Vec<AST>> {
+    let mut parser = Parser {
+        iter: input.into_iter().peekable(),
+        options,
+        positions: None
+    };
+    parser.program()
+}
+/// Like `parse_with`, but takes tokens with positions. Each statement is
+/// wrapped in `AST::Located`, and errors point at the token where they
+/// happened.
+pub fn parse_located<I>(input: I, options: Options) -> StdResult<Vec<AST>, Located<Error>>
+    where I: IntoIterator<Item = Located<Token>>
+{
+    let last = Rc::new(Cell::new((1, 1, 0)));
+    let mut parser = Parser {
+        iter: Positions { iter: input.into_iter(), last: Rc::clone(&last) }.peekable(),
+        options,
+        positions: Some(Rc::clone(&last))
+    };
+    parser.program().map_err(|inner| {
+        let (line, col, offset) = last.get();
+        Located { line, col, offset, inner }
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokenizer::{tokenize_located, tokenize_str};
+
+    fn parse_str(code: &str) -> Vec<AST> {
+        parse(tokenize_str(code).unwrap()).unwrap()
+    }
 
     #[test]
     fn assign() {
@@ -445,6 +811,30 @@ mod tests {
         );
     }
     #[test]
+    fn typed_declarations() {
+        let declare = |value| vec![AST::IHasA("COUNT".to_string(), Expr::Value(value))];
+        assert_eq!(parse_str("I HAS A COUNT ITZ A NUMBR"), declare(Value::Numbr(0)));
+        assert_eq!(parse_str("I HAS A COUNT ITZ A NUMBAR"), declare(Value::Numbar(0.0)));
+        assert_eq!(parse_str("I HAS A COUNT ITZ A YARN"), declare(Value::Yarn(String::new())));
+        assert_eq!(parse_str("I HAS A COUNT ITZ A TROOF"), declare(Value::Troof(false)));
+        assert_eq!(parse_str("I HAS A COUNT ITZ A NOOB"), declare(Value::Noob));
+        assert_eq!(parse_str("I HAS A COUNT ITZ A BUKKIT"), declare(Value::Bukkit(Vec::new())));
+        match parse(vec![Token::IHasA, Token::Ident("COUNT".to_string()), Token::Itz, Token::A, Token::Mkay]) {
+            Err(Error::ExpectedKind("type")) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn declare_with_r() {
+        let tokens = tokenize_str("I HAS A X R SUM OF 1 AN 2").unwrap();
+        match parse(tokens.clone()) {
+            Err(Error::Trailing) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        let options = Options { declare_with_r: true, ..Options::default() };
+        assert_eq!(parse_with(tokens, options).unwrap(), parse_str("I HAS A X ITZ SUM OF 1 AN 2"));
+    }
+    #[test]
     fn troofs() {
         assert_eq!(
             parse(vec![
@@ -462,6 +852,229 @@ mod tests {
         )
     }
     #[test]
+    fn nested_math() {
+        let num = |n| Box::new(Expr::Value(Value::Numbr(n)));
+        assert_eq!(
+            parse_str("SUM OF PRODUKT OF 2 AN 3 AN 4"),
+            &[AST::It(Expr::SumOf(Box::new(Expr::ProduktOf(num(2), num(3))), num(4)))]
+        );
+        assert_eq!(
+            parse_str("SUM OF 4 AN PRODUKT OF 2 AN 3"),
+            &[AST::It(Expr::SumOf(num(4), Box::new(Expr::ProduktOf(num(2), num(3)))))]
+        );
+        assert_eq!(
+            parse_str("DIFF OF SUM OF PRODUKT OF 2 AN 3 AN 4 AN BOTH SAEM 1 AN NOT 2"),
+            &[AST::It(Expr::DiffOf(
+                Box::new(Expr::SumOf(Box::new(Expr::ProduktOf(num(2), num(3))), num(4))),
+                Box::new(Expr::BothSaem(num(1), Box::new(Expr::Not(num(2)))))
+            ))]
+        );
+        // NOT applies to the whole comparison, not just its first operand
+        assert_eq!(
+            parse_str("NOT DIFFRINT 1 AN 2"),
+            &[AST::It(Expr::Not(Box::new(Expr::Diffrint(num(1), num(2)))))]
+        );
+        assert_eq!(
+            parse_str("BOTH OF NOT BOTH SAEM 1 AN 2 AN 3"),
+            &[AST::It(Expr::BothOf(Box::new(Expr::Not(Box::new(Expr::BothSaem(num(1), num(2))))), num(3)))]
+        );
+        // AN is optional
+        assert_eq!(parse_str("SUM OF DIFF OF 1 2 3"), parse_str("SUM OF DIFF OF 1 AN 2 AN 3"));
+    }
+    #[test]
+    fn variadic() {
+        let win = || Token::Value(Value::Troof(true));
+        assert_eq!(
+            parse(vec![
+                Token::AnyOf, win(), Token::An, win(), Token::An, win(), Token::Mkay, Token::Separator,
+                Token::AllOf, win(), Token::An, win(), Token::Separator,
+                Token::Visible, Token::AllOf, win(), Token::Mkay, win()
+            ]).unwrap(),
+            &[AST::It(Expr::AnyOf(vec![Expr::Value(Value::Troof(true)); 3])),
+              AST::It(Expr::AllOf(vec![Expr::Value(Value::Troof(true)); 2])),
+              AST::Visible(vec![Expr::AllOf(vec![Expr::Value(Value::Troof(true))]),
+                                Expr::Value(Value::Troof(true))], true)]
+        );
+        // MKAY ends the SMOOSH, so the outer expression can go on
+        let yarn = |s: &str| Expr::Value(Value::Yarn(s.to_string()));
+        assert_eq!(
+            parse_str("BOTH SAEM SMOOSH \"a\" AN \"b\" MKAY AN \"ab\""),
+            &[AST::It(Expr::BothSaem(Box::new(Expr::Smoosh(vec![yarn("a"), yarn("b")])), Box::new(yarn("ab"))))]
+        );
+        assert_eq!(
+            parse_str("SMOOSH \"a\" AN SMOOSH \"b\" AN \"c\" MKAY AN \"d\""),
+            &[AST::It(Expr::Smoosh(vec![yarn("a"), Expr::Smoosh(vec![yarn("b"), yarn("c")]), yarn("d")]))]
+        );
+        // Without it, the SMOOSH takes the rest of the line
+        assert!(parse(tokenize_str("BOTH SAEM SMOOSH \"a\" AN \"b\" AN \"ab\"").unwrap()).is_err());
+    }
+    #[test]
+    fn slots() {
+        let ident = |name: &str| Token::Ident(name.to_string());
+        let slot = |bukkit, key: &str| Expr::Slot(Box::new(bukkit), key.to_string());
+        assert_eq!(
+            parse(vec![
+                ident("a"), Token::ApostropheZ, ident("b"), Token::ApostropheZ, ident("c"),
+                    Token::R, ident("a"), Token::ApostropheZ, Token::Value(Value::Numbr(0)), Token::Separator,
+                Token::Visible, ident("a"), Token::ApostropheZ, ident("b")
+            ]).unwrap(),
+            &[AST::RSlot(slot(Expr::Var("a".to_string()), "b"), "c".to_string(), slot(Expr::Var("a".to_string()), "0")),
+              AST::Visible(vec![slot(Expr::Var("a".to_string()), "b")], true)]
+        );
+    }
+    #[test]
+    fn visible() {
+        assert_eq!(
+            parse(vec![
+                Token::Visible, Token::Value(Value::Yarn("x".to_string())), Token::Separator,
+                Token::Visible, Token::Value(Value::Yarn("x".to_string())), Token::Exclamation, Token::Separator,
+                Token::Visible, Token::Value(Value::Yarn("a".to_string())), Token::Value(Value::Yarn("b".to_string())),
+                    Token::Exclamation
+            ]).unwrap(),
+            &[AST::Visible(vec![Expr::Value(Value::Yarn("x".to_string()))], true),
+              AST::Visible(vec![Expr::Value(Value::Yarn("x".to_string()))], false),
+              AST::Visible(vec![Expr::Value(Value::Yarn("a".to_string())),
+                                Expr::Value(Value::Yarn("b".to_string()))], false)]
+        );
+    }
+    #[test]
+    fn display() {
+        let expr = Expr::AllOf(vec![
+            Expr::Not(Box::new(Expr::BothSaem(
+                Box::new(Expr::SumOf(Box::new(Expr::Var("X".to_string())), Box::new(Expr::Value(Value::Numbar(1.0))))),
+                Box::new(Expr::IIz("FUNC".to_string(), vec![Expr::It, Expr::Value(Value::Troof(true))]))
+            ))),
+            Expr::Smoosh(vec![
+                Expr::Value(Value::Yarn("a:\"b\"\n".to_string())),
+                Expr::Value(Value::YarnRaw(vec![Interpolate::Var("X".to_string()), Interpolate::Str("!".to_string())]))
+            ])
+        ]);
+        assert_eq!(
+            expr.to_string(),
+            "ALL OF NOT BOTH SAEM SUM OF X AN 1.0 AN I IZ FUNC YR IT AN YR WIN MKAY \
+             AN SMOOSH \"a:::\"b:\":)\" AN \":{X}!\" MKAY MKAY"
+        );
+        assert_eq!(
+            Expr::Maek(Box::new(Expr::Srs(Box::new(Expr::Var("NAME".to_string())))), Type::Troof).to_string(),
+            "MAEK SRS NAME A TROOF"
+        );
+    }
+    #[test]
+    fn clone_eq() {
+        for code in &[include_str!("../tests/fac.lol"), include_str!("../tests/quine.lol"), include_str!("../tests/pow.lol")] {
+            let program = parse_str(code);
+            let copy = program.clone();
+            assert_eq!(copy, program);
+        }
+        let mut program = parse_str("I HAS A B ITZ A BUKKIT, B'Z X R SUM OF 1 AN 2, WIN, O RLY?, YA RLY, GTFO, OIC");
+        let original = program.clone();
+        if let AST::ORly(ref mut yes, _, _) = program[3] {
+            yes.clear();
+        }
+        assert_ne!(program, original);
+    }
+    #[test]
+    fn round_trip() {
+        let program = parse_str("\
+HAI 1.2
+I HAS A X ITZ SUM OF 2 AN 4
+I HAS A Y, I HAS A B ITZ A BUKKIT
+B'Z KEY R \"a:::)\", SRS \"X\" R MAEK X A YARN
+HOW IZ I FUNC YR A AN YR C
+    BOTH SAEM A AN C, O RLY?
+        YA RLY, FOUND YR A
+        MEBBE DIFFRINT A AN 1
+            GTFO
+        NO WAI, INVISIBLE \":{A}\" AN SMOOSH 1 AN 2 MKAY !
+    OIC
+IF U SAY SO
+IM IN YR LOOP UPPIN YR I TIL BOTH SAEM I AN 3
+    X, WTF?
+    OMG 1, VISIBLE I
+    OMG 2, GTFO
+    OMGWTF, VISIBLE I IZ FUNC YR I AN YR B'Z KEY MKAY, ALL OF WIN AN FAIL
+    OIC
+IM OUTTA YR LOOP
+GIMMEH Y
+KTHXBYE");
+        let printed: Vec<_> = program.iter().map(|ast| ast.to_string()).collect();
+        let printed = printed.join("\n");
+        assert_eq!(parse_str(&printed), program, "printed:\n{}", printed);
+        assert_eq!(program[0].to_string(), "I HAS A X ITZ SUM OF 2 AN 4");
+    }
+    #[test]
+    fn separators() {
+        let tokens = tokenize_str("BOTH SAEM 1 AN 1 O RLY?\nYA RLY, I HAS A X ITZ 1 X R 2\nOIC").unwrap();
+        match parse(tokens.clone()) {
+            Err(Error::MissingSeparator(Token::ORly)) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        let options = Options { lenient: true, ..Options::default() };
+        assert_eq!(
+            parse_with(tokens, options).unwrap(),
+            parse_str("BOTH SAEM 1 AN 1, O RLY?\nYA RLY, I HAS A X ITZ 1, X R 2\nOIC")
+        );
+        match parse_with(vec![Token::Hai, Token::Value(Value::Numbar(1.2)), Token::Gtfo], options) {
+            Err(Error::MissingSeparator(Token::Gtfo)) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn hai_kthxbye() {
+        assert_eq!(parse_str("HAI 1.2 BTW start\nVISIBLE 1\nKTHXBYE BTW end"), parse_str("VISIBLE 1"));
+        assert_eq!(parse_str("HAI 1.2, VISIBLE 1, KTHXBYE"), parse_str("VISIBLE 1"));
+        for &lenient in &[false, true] {
+            let options = Options { lenient, ..Options::default() };
+            match parse_with(tokenize_str("HAI 1.2\nKTHXBYE extra").unwrap(), options) {
+                Err(Error::MissingSeparator(Token::Ident(ref ident))) if ident == "extra" => (),
+                result => panic!("unexpected result: {:?}", result)
+            }
+        }
+        match parse(tokenize_str("HAI\nVISIBLE 1").unwrap()) {
+            Err(Error::ExpectedKind("numbar")) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn located() {
+        fn at(line: usize, col: usize, offset: usize, inner: AST) -> AST {
+            AST::Located(Box::new(Located { line, col, offset, inner }))
+        }
+        let code = "HAI 1.2\nVISIBLE X\nO RLY?\n  YA RLY, GTFO\nOIC";
+        let parsed = parse_located(tokenize_located(code).unwrap(), Options::default()).unwrap();
+        assert_eq!(parsed, &[
+            at(2, 1, 8, AST::Visible(vec![Expr::Var("X".to_string())], true)),
+            at(3, 1, 18, AST::ORly(vec![at(4, 11, 35, AST::Gtfo)], Vec::new(), Vec::new()))
+        ]);
+        assert_eq!(parsed[0].unlocated(), &AST::Visible(vec![Expr::Var("X".to_string())], true));
+        assert_eq!(parsed[0].to_string(), "VISIBLE X");
+
+        match parse_located(tokenize_located("VISIBLE X\nI HAS A 1").unwrap(), Options::default()) {
+            Err(Located { line: 2, col: 9, offset: 18, inner: Error::ExpectedKind("identifier") }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        match parse_located(tokenize_located("VISIBLE X\nVISIBLE\n").unwrap(), Options::default()) {
+            Err(Located { line: 2, col: 8, inner: Error::ExpectedKind("expression"), .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn stray_mkay() {
+        for code in &[
+            "VISIBLE \"x\"\nMKAY\n",
+            "VISIBLE \"x\" MKAY",
+            "I HAS A X ITZ SUM OF 1 AN 2 MKAY",
+            "VISIBLE SMOOSH 1 AN 2 MKAY MKAY",
+            "MKAY"
+        ] {
+            match parse(tokenize_str(code).unwrap()) {
+                Err(Error::UnexpectedMkay) => (),
+                result => panic!("unexpected result for {:?}: {:?}", code, result)
+            }
+        }
+        assert_eq!(parse_str("VISIBLE SMOOSH 1 AN 2 MKAY\n").len(), 1);
+    }
+    #[test]
     fn nested_orlys() {
         assert_eq!(
             parse(vec![
@@ -0,0 +1,83 @@
+#[cfg(test)] use std::cell::Cell;
+use std::{cell::RefCell, rc::Rc};
+#[cfg(not(target_arch = "wasm32"))] use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of random numbers. All randomness in the interpreter goes
+/// through one of these, so seeding it makes programs reproducible.
+pub trait RngCore {
+    fn next_u64(&mut self) -> u64;
+}
+
+/// The RNG shared between the evaluator and native functions
+pub type SharedRng = Rc<RefCell<Box<dyn RngCore>>>;
+
+/// A small, seedable xorshift generator. Not cryptographically secure.
+#[derive(Clone, Debug)]
+pub struct XorShift(u64);
+impl XorShift {
+    /// Create a new generator from `seed`
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        XorShift(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+    }
+    /// Create a new generator seeded from the current time.
+    /// Not available on wasm, where std has no clock.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_time() -> Self {
+        #[cfg(test)] CLOCK_READS.with(|reads| reads.set(reads.get() + 1));
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self::new(time.as_secs() ^ (u64::from(time.subsec_nanos()) << 32))
+    }
+}
+impl RngCore for XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+// How often from_time ran on this thread, so tests can check nothing reads the clock
+thread_local!(pub static CLOCK_READS: Cell<usize> = const { Cell::new(0) });
+
+/// The generator programs get by default. It's only seeded from the
+/// current time once the first random number is needed, so creating an
+/// evaluator never touches the clock. On wasm it uses a fixed seed instead.
+#[derive(Clone, Debug, Default)]
+pub struct LazyRng(Option<XorShift>);
+impl RngCore for LazyRng {
+    fn next_u64(&mut self) -> u64 {
+        #[cfg(not(target_arch = "wasm32"))]
+        let seed = XorShift::from_time;
+        #[cfg(target_arch = "wasm32")]
+        let seed = || XorShift::new(0);
+        self.0.get_or_insert_with(seed).next_u64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let mut one = XorShift::new(42);
+        let mut two = XorShift::new(42);
+        for _ in 0..10 {
+            assert_eq!(one.next_u64(), two.next_u64());
+        }
+        assert_ne!(XorShift::new(1).next_u64(), XorShift::new(2).next_u64());
+        assert_ne!(XorShift::new(0).next_u64(), 0);
+    }
+    #[test]
+    fn lazy() {
+        let reads = CLOCK_READS.with(Cell::get);
+        let mut rng = LazyRng::default();
+        assert_eq!(CLOCK_READS.with(Cell::get), reads);
+        rng.next_u64();
+        rng.next_u64();
+        assert_eq!(CLOCK_READS.with(Cell::get), reads + 1);
+    }
+}
@@ -0,0 +1,159 @@
+use eval::{EvalParams, Error};
+use std::io;
+use types::{Type, Value};
+
+fn yarn<F>(values: &[Value], apply: F) -> Value
+    where F: FnOnce(&str) -> String
+{
+    match values[0].cast_yarn() {
+        Some(yarn) => Value::Yarn(apply(&yarn)),
+        None => Value::Noob
+    }
+}
+
+/// Bind all standard library functions.
+/// This is opt-in because library functions take precedence over
+/// functions defined in LOLCODE.
+pub fn bind<R: io::BufRead, W: io::Write>(eval: &mut EvalParams<R, W>) {
+    eval.bind_func("UPPERCASE", Some(1), |values| yarn(&values, str::to_uppercase));
+    eval.bind_func("LOWERCASE", Some(1), |values| yarn(&values, str::to_lowercase));
+    eval.bind_func("TRIM", Some(1), |values| yarn(&values, |yarn| yarn.trim().to_string()));
+    eval.bind_func("CHARS", Some(1), |values| match values[0].cast_yarn() {
+        Some(yarn) => Value::Bukkit(yarn.chars()
+            .enumerate()
+            .map(|(i, c)| (i.to_string(), Value::Yarn(c.to_string())))
+            .collect()),
+        None => Value::Noob
+    });
+
+    eval.try_bind_func("ASSERT", None, |values| {
+        if values.is_empty() || values.len() > 2 {
+            return Err(Error::InvalidUsageRange("ASSERT".to_string(), 1, 2, values.len()));
+        }
+        if values[0].cast_troof() {
+            return Ok(Value::Noob);
+        }
+        Err(Error::AssertionFailed(match values.get(1).and_then(Value::cast_yarn) {
+            Some(message) => message.into_owned(),
+            None => "got FAIL".to_string()
+        }))
+    });
+    // Indexes start at 0, like the keys from CHARS
+    eval.try_bind_func("AT", Some(2), |values| {
+        let yarn = values[0].cast_yarn().ok_or(Error::BadCast { from: values[0].kind(), to: Type::Yarn })?;
+        let index = values[1].cast_numbr().ok_or(Error::BadCast { from: values[1].kind(), to: Type::Numbr })?;
+        let len = yarn.chars().count();
+        if index < 0 {
+            return Err(Error::IndexOutOfRange(index, len));
+        }
+        match yarn.chars().nth(index as usize) {
+            Some(c) => Ok(Value::Yarn(c.to_string())),
+            None => Err(Error::IndexOutOfRange(index, len))
+        }
+    });
+
+    let rng = eval.rng();
+    eval.bind_func("WHATEVR", Some(0), move |_| Value::Numbr((rng.borrow_mut().next_u64() >> 1) as i64));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse;
+    use rng::{RngCore, XorShift, CLOCK_READS};
+    use std::cell::Cell;
+
+    fn try_run(code: &str) -> Result<String, Error> {
+        let mut output = Vec::new();
+        {
+            let mut eval = EvalParams::new(io::empty(), &mut output);
+            bind(&mut eval);
+            eval.set_rng(XorShift::new(1234));
+            eval.scope().eval_all(parse(code).unwrap())?;
+        }
+        Ok(String::from_utf8(output).unwrap())
+    }
+    fn run(code: &str) -> String {
+        try_run(code).expect("Running test failed")
+    }
+
+    #[test]
+    fn casing() {
+        assert_eq!(run("VISIBLE I IZ UPPERCASE YR \"  MiXeD straße \" MKAY"), "  MIXED STRASSE \n");
+        assert_eq!(run("VISIBLE I IZ LOWERCASE YR \"  MiXeD ÄÖÜ \" MKAY"), "  mixed äöü \n");
+    }
+    #[test]
+    fn whatevr() {
+        let code = "VISIBLE I IZ WHATEVR MKAY \" \" I IZ WHATEVR MKAY";
+        let output = run(code);
+        assert_eq!(output, run(code));
+
+        let mut rng = XorShift::new(1234);
+        let one = (rng.next_u64() >> 1) as i64;
+        let two = (rng.next_u64() >> 1) as i64;
+        assert_eq!(output, format!("{} {}\n", one, two));
+    }
+    #[test]
+    fn default_rng() {
+        let reads = || CLOCK_READS.with(Cell::get);
+        let before = reads();
+        let mut eval = EvalParams::new(io::empty(), io::sink());
+        bind(&mut eval);
+        let scope = eval.scope();
+        scope.eval_all(parse("VISIBLE \"no randomness here\"").unwrap()).expect("Running test failed");
+        assert_eq!(reads(), before);
+        // Only the first random number seeds it
+        scope.eval_all(parse("I IZ WHATEVR MKAY, I IZ WHATEVR MKAY").unwrap()).expect("Running test failed");
+        assert_eq!(reads(), before + 1);
+    }
+    #[test]
+    fn chars() {
+        let mut eval = EvalParams::new(io::empty(), io::sink());
+        bind(&mut eval);
+        let scope = eval.scope();
+        scope.eval_all(parse("I HAS A X ITZ I IZ CHARS YR \"abc\" MKAY").unwrap()).expect("Running test failed");
+        assert_eq!(scope.find_var("X", |var| var.clone()), Some(Value::Bukkit(vec![
+            ("0".to_string(), Value::Yarn("a".to_string())),
+            ("1".to_string(), Value::Yarn("b".to_string())),
+            ("2".to_string(), Value::Yarn("c".to_string()))
+        ])));
+    }
+    #[test]
+    fn assert() {
+        assert_eq!(run("I IZ ASSERT YR BOTH SAEM 1 AN 1 MKAY, I IZ ASSERT YR WIN AN YR \"msg\" MKAY, VISIBLE \"ok\""), "ok\n");
+        for &(code, expected) in &[
+            ("I IZ ASSERT YR BOTH SAEM 1 AN 2 AN YR \"1 is not 2\" MKAY", "1 is not 2"),
+            ("I IZ ASSERT YR FAIL MKAY", "got FAIL"),
+            ("I IZ ASSERT YR \"\" MKAY", "got FAIL")
+        ] {
+            match try_run(code) {
+                Err(Error::AssertionFailed(ref message)) if message == expected => (),
+                result => panic!("unexpected result for {}: {:?}", code, result.map_err(|err| err.to_string()))
+            }
+        }
+        for &(code, got) in &[
+            ("I IZ ASSERT MKAY", 0),
+            ("I IZ ASSERT YR WIN AN YR \"a\" AN YR \"b\" MKAY", 3)
+        ] {
+            match try_run(code) {
+                Err(Error::InvalidUsageRange(ref name, 1, 2, n)) if name == "ASSERT" && n == got => (),
+                result => panic!("unexpected result for {}: {:?}", code, result.map_err(|err| err.to_string()))
+            }
+        }
+    }
+    #[test]
+    fn at() {
+        assert_eq!(run("VISIBLE I IZ AT YR \"a☃ß\" AN YR 0 MKAY I IZ AT YR \"a☃ß\" AN YR 1 MKAY"), "a☃\n");
+        assert_eq!(run("VISIBLE I IZ AT YR \"a☃ß\" AN YR \"2\" MKAY"), "ß\n");
+        for &index in &[3, -1] {
+            match try_run(&format!("VISIBLE I IZ AT YR \"a☃ß\" AN YR {} MKAY", index)) {
+                Err(Error::IndexOutOfRange(got, 3)) if got == index => (),
+                result => panic!("unexpected result: {:?}", result.map_err(|err| err.to_string()))
+            }
+        }
+    }
+    #[test]
+    fn trim() {
+        assert_eq!(run("VISIBLE I IZ TRIM YR \" \t MiXeD Case  \" MKAY \"|\""), "MiXeD Case|\n");
+    }
+}
@@ -1,13 +1,19 @@
 use std::{
+    borrow::Cow,
     char as stdchar,
+    fmt,
+    io,
     iter::Peekable,
+    mem,
     result::Result as StdResult
 };
-use types::{Interpolate, Value};
+use types::{Interpolate, Type, Value};
 use unic_ucd_name::Name as UnicName;
 
 #[derive(Debug, Fail)]
 pub enum Error {
+    #[fail(display = "input looks like binary data, not LOLCODE")]
+    BinaryInput,
     #[fail(display = "invalid character in identifier: {}", _0)]
     InvalidIdent(char),
     #[fail(display = "invalid characters in interpolation: {:?}", _0)]
@@ -16,6 +22,8 @@ pub enum Error {
     InvalidNumber(String),
     #[fail(display = "invalid unicode character: {}", _0)]
     InvalidUnicode(String),
+    #[fail(display = "failed to write trace: {}", _0)]
+    TraceError(io::Error),
     #[fail(display = "unclosed comment")]
     UnclosedComment,
     #[fail(display = "unclosed interpolation in string")]
@@ -26,6 +34,11 @@ pub enum Error {
     UnexpectedEOF,
     #[fail(display = "unknown escape character: {}", _0)]
     UnknownEscape(char),
+    #[fail(display = "unknown keyword {:?}, did you mean {}?", got, suggestion)]
+    UnknownKeyword {
+        got: String,
+        suggestion: &'static str
+    },
     #[fail(display = "unknown token")]
     UnknownToken,
 }
@@ -33,19 +46,30 @@ pub enum Error {
 type Result<T> = StdResult<T, Error>;
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(tag = "kind", content = "value"))]
 pub enum Token {
     It,
     Ident(String),
     Value(Value),
+    Type(Type),
 
     Hai,
     KThxBye,
     Separator,
+    /// A line break, only with `Options::detailed_separators`
+    Newline,
+    /// A comma, only with `Options::detailed_separators`
+    Comma,
 
     IHasA,
     Itz,
+    A,
     R,
 
+    ApostropheZ,
+    Srs,
+    Maek,
+
     SumOf,
     DiffOf,
     ProduktOf,
@@ -92,32 +116,255 @@ pub enum Token {
     IIz,
 
     Visible,
+    Invisible,
     Exclamation,
     Gimmeh
 }
 
+/// Options for tweaking the language accepted by the tokenizer
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The keyword starting a comment that lasts until the end of the line
+    pub comment: Cow<'static, str>,
+    /// The keyword starting a multiline comment
+    pub comment_start: Cow<'static, str>,
+    /// The keyword ending a multiline comment
+    pub comment_end: Cow<'static, str>,
+    /// Reject misspelled keywords, like the `VISBLE` in `VISBLE X`. Only
+    /// words starting a statement are checked, and only where an
+    /// identifier couldn't be, so names like `NUMBRS` still work.
+    pub strict: bool,
+    /// Check the start of the input for binary data before tokenizing
+    pub reject_binary: bool,
+    /// Accept `O RLY ?` and `WTF ?`, with the `?` as a separate word
+    pub spaced_question_marks: bool,
+    /// Emit `Newline` and `Comma` instead of `Separator`, for tools like
+    /// formatters. The parser only understands `Separator`.
+    pub detailed_separators: bool,
+    /// Match keywords regardless of ASCII case, so `visible` is `VISIBLE`.
+    /// Identifiers are still case sensitive, but words like `it` or
+    /// `an` can no longer be used as identifiers.
+    pub case_insensitive: bool
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            comment: "BTW".into(),
+            comment_start: "OBTW".into(),
+            comment_end: "TLDR".into(),
+            strict: false,
+            reject_binary: false,
+            spaced_question_marks: false,
+            detailed_separators: false,
+            case_insensitive: false
+        }
+    }
+}
+
+/// Something found at a position in the input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Located<T> {
+    pub line: usize,
+    pub col: usize,
+    /// Byte offset from the start of the input
+    pub offset: usize,
+    pub inner: T
+}
+
+/// Something covering a range of bytes in the input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub start: usize,
+    pub end: usize
+}
+
 #[derive(Clone)]
 pub struct Tokenizer<I: Iterator<Item = char> + Clone> {
-    pub iter: Peekable<I>
+    pub iter: Peekable<I>,
+    pub options: Options,
+    line: usize,
+    col: usize,
+    offset: usize,
+    /// The offset after the last character that wasn't whitespace
+    end: usize,
+    /// The line, column and offset of the token being read, after any
+    /// spaces and comments before it
+    start: (usize, usize, usize),
+    /// Whether the next token starts a statement
+    statement_start: bool,
+    prev: Option<char>
 }
 
+/// Whitespace between words. Line breaks aren't included, since they
+/// separate statements.
 fn is_space(c: char) -> bool {
-    c == ' ' || c == '\t'
+    c.is_whitespace() && c != '\n' && c != '\r'
+}
+
+/// Every word that's part of a keyword, used to catch typos in strict mode
+const KEYWORDS: &[&str] = &[
+    "HAI", "KTHXBYE", "WIN", "FAIL", "IT", "HAS", "IZ", "ITZ", "SRS",
+    "SUM", "DIFF", "PRODUKT", "QUOSHUNT", "MOD", "BIGGR", "SMALLR", "OF",
+    "BOTH", "EITHER", "WON", "NOT", "ALL", "ANY", "SAEM", "DIFFRINT",
+    "SMOOSH", "AN", "MKAY", "RLY?", "RLY", "MEBBE", "WAI", "OIC",
+    "WTF?", "OMG", "OMGWTF", "GTFO", "IM", "IN", "OUTTA", "YR",
+    "UPPIN", "NERFIN", "TIL", "WILE", "HOW", "IF", "SAY", "SO", "FOUND",
+    "VISIBLE", "INVISIBLE", "GIMMEH",
+    "NOOB", "TROOF", "NUMBR", "NUMBAR", "YARN", "BUKKIT"
+];
+
+/// Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + (ca != cb) as usize);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// A keyword `word` is probably a misspelling of. Short keywords are
+/// skipped since they'd match too many ordinary identifiers.
+fn suggest_keyword(word: &str) -> Option<&'static str> {
+    KEYWORDS.iter()
+        .filter(|keyword| keyword.len() >= 4)
+        .find(|keyword| edit_distance(word, keyword) == 1)
+        .cloned()
+}
+
+/// The type a word names, if any
+fn type_of(word: &str) -> Option<Type> {
+    match word {
+        "NOOB" => Some(Type::Noob),
+        "TROOF" => Some(Type::Troof),
+        "NUMBR" => Some(Type::Numbr),
+        "NUMBAR" => Some(Type::Numbar),
+        "YARN" => Some(Type::Yarn),
+        "BUKKIT" => Some(Type::Bukkit),
+        _ => None
+    }
 }
 
 impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
+    /// Create a new tokenizer with the default options
+    pub fn new(iter: I) -> Self {
+        Self::with_options(iter, Options::default())
+    }
+    /// Create a new tokenizer with the specified options
+    pub fn with_options(iter: I, options: Options) -> Self {
+        let mut tokenizer = Self {
+            iter: iter.peekable(),
+            options,
+            line: 1,
+            col: 1,
+            offset: 0,
+            end: 0,
+            start: (1, 1, 0),
+            statement_start: true,
+            prev: None
+        };
+        // Skip a byte order mark, which some editors add. It still
+        // counts towards byte offsets, but not columns.
+        if tokenizer.iter.peek() == Some(&'\u{FEFF}') {
+            tokenizer.iter.next();
+            tokenizer.offset = '\u{FEFF}'.len_utf8();
+        }
+        tokenizer
+    }
+    /// Consume one character, keeping track of the position
+    fn bump(&mut self) -> Option<char> {
+        let c = self.iter.next();
+        match c {
+            // The \r already started a new line
+            Some('\n') if self.prev == Some('\r') => (),
+            Some('\n') | Some('\r') => {
+                self.line += 1;
+                self.col = 1;
+            },
+            Some(_) => self.col += 1,
+            None => ()
+        }
+        if let Some(c) = c {
+            self.offset += c.len_utf8();
+            if !is_space(c) {
+                self.end = self.offset;
+            }
+        }
+        self.prev = c;
+        c
+    }
+    /// The line and column of the next character, both starting at 1
+    pub fn position(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+    /// The byte offset of the next character
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Stop tokenizing and get back the input that hasn't been read yet.
+    /// Spaces after the last token may already have been skipped.
+    pub fn into_inner(self) -> Peekable<I> {
+        self.iter
+    }
+    /// Attach the start of the last token read
+    fn locate<T>(&self) -> impl FnOnce(T) -> Located<T> {
+        let (line, col, offset) = self.start;
+        move |inner| Located { line, col, offset, inner }
+    }
+    fn read_until(&mut self, c: char) -> Result<String> {
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Err(Error::UnclosedInterpolation),
+                None => return Err(Error::UnclosedString),
+
+                Some(c2) if c == c2 => break,
+                Some(c) => string.push(c)
+            }
+        }
+        Ok(string)
+    }
     fn trim(&mut self) {
         loop {
             match self.iter.peek().cloned() {
-                Some(c) if is_space(c) => { self.iter.next(); },
+                Some(c) if is_space(c) => { self.bump(); },
                 _ => break
             }
         }
     }
+    /// Skip the rest of the statement, up to the next separator
+    fn skip_statement(&mut self) {
+        loop {
+            match self.iter.peek().cloned() {
+                None | Some('\n') | Some('\r') | Some(',') => break,
+                Some(_) => { self.bump(); }
+            }
+        }
+    }
     fn peek(&mut self) -> Option<char> {
         self.trim();
         self.iter.peek().cloned()
     }
+    /// Read a word to compare against keywords
+    fn keyword(&mut self) -> String {
+        let mut word = self.word();
+        // Keywords are ASCII, and full Unicode uppercasing would turn
+        // words like `ſum` into one
+        if self.options.case_insensitive {
+            word.make_ascii_uppercase();
+        }
+        word
+    }
+    fn is_keyword(&self, word: &str, keyword: &str) -> bool {
+        if self.options.case_insensitive { word.eq_ignore_ascii_case(keyword) } else { word == keyword }
+    }
     fn word(&mut self) -> String {
         let mut word = String::new();
         loop {
@@ -126,47 +373,65 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
                     self.trim();
                     return word;
                 },
-                None | Some('\n') | Some(',') => return word,
+                None | Some('\n') | Some('\r') | Some(',') | Some('\'') => return word,
+                // A trailing ! is its own token, as in VISIBLE X!
+                Some('!') if !word.is_empty() => {
+                    let mut clone = self.iter.clone();
+                    clone.next();
+                    match clone.peek().cloned() {
+                        None | Some('\n') | Some('\r') | Some(',') => return word,
+                        Some(c) if is_space(c) => return word,
+                        _ => {
+                            self.bump();
+                            word.push('!');
+                        }
+                    }
+                },
                 Some(c) => {
-                    self.iter.next();
+                    self.bump();
                     word.push(c);
                 }
             }
         }
     }
+    /// Read one token from the input, along with where it starts
+    pub fn next_located(&mut self) -> Result<Option<Located<Token>>> {
+        let token = self.next()?;
+        Ok(token.map(self.locate()))
+    }
+    fn separator(&self, detailed: Token) -> Token {
+        if self.options.detailed_separators { detailed } else { Token::Separator }
+    }
+    /// Read one token from the input, along with the bytes it covers
+    pub fn next_spanned(&mut self) -> Result<Option<Spanned<Token>>> {
+        let token = self.next()?;
+        let (_, _, start) = self.start;
+        Ok(token.map(|node| Spanned { node, start, end: self.end }))
+    }
     /// Read one token from the input
+    // Not an Iterator, since reading a token can fail
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Result<Option<Token>> {
         let c = match self.peek() {
             Some(c) => c,
             None => return Ok(None)
         };
+        self.start = (self.line, self.col, self.offset);
+        let statement_start = mem::replace(&mut self.statement_start, false);
         if c == '"' {
-            fn read_until<I: Iterator<Item = char>>(iter: &mut I, c: char) -> Result<String> {
-                let mut string = String::new();
-                loop {
-                    match iter.next() {
-                        Some('"') => return Err(Error::UnclosedInterpolation),
-                        None => return Err(Error::UnclosedString),
-
-                        Some(c2) if c == c2 => break,
-                        Some(c) => string.push(c)
-                    }
-                }
-                Ok(string)
-            }
-            self.iter.next(); // leading "
+            self.bump(); // leading "
             let mut interpolated = Vec::new();
             let mut string = String::new();
-            while let Some(c) = self.iter.next() {
+            while let Some(c) = self.bump() {
                 if c == ':' {
-                    match self.iter.next() {
+                    match self.bump() {
                         Some(')') => string.push('\n'),
                         Some('>') => string.push('\t'),
                         Some('o') => string.push('\x07'),
                         Some('"') => string.push('"'),
                         Some(':') => string.push(':'),
                         Some('(') => {
-                            let mut hex = read_until(&mut self.iter, ')')?;
+                            let hex = self.read_until(')')?;
                             let num = match u32::from_str_radix(&hex, 16) {
                                 Ok(num) => num,
                                 Err(_) => return Err(Error::InvalidNumber(hex))
@@ -177,15 +442,13 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
                             }
                         },
                         Some('{') => {
-                            let mut var = read_until(&mut self.iter, '}')?;
+                            let var = self.read_until('}')?;
                             match var.chars().next() {
                                 None |
-                                Some('0'...'9') => return Err(Error::InvalidInterpolation(var)),
+                                Some('0'..='9') => return Err(Error::InvalidInterpolation(var)),
                                 _ => ()
                             }
-                            if !var.chars().all(|c|
-                                    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') ||
-                                    (c >= '0' && c <= '9') || c == '_') {
+                            if !var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
                                 return Err(Error::InvalidInterpolation(var));
                             }
                             if !string.is_empty() {
@@ -195,7 +458,7 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
                             string = String::new();
                         },
                         Some('[') => {
-                            let mut name = read_until(&mut self.iter, ']')?.to_uppercase();
+                            let name = self.read_until(']')?.to_uppercase();
                             let mut unicode = None;
                             for c in chars!(..) {
                                 if UnicName::of(c)
@@ -228,45 +491,75 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
                 return Ok(Some(Token::Value(Value::YarnRaw(interpolated))));
             }
         } else if c == '\n' || c == ',' {
-            self.iter.next();
-            return Ok(Some(Token::Separator));
+            self.bump();
+            self.statement_start = true;
+            return Ok(Some(self.separator(if c == ',' { Token::Comma } else { Token::Newline })));
+        } else if c == '\r' {
+            // Treat both \r\n and a lone \r like \n
+            self.bump();
+            if self.iter.peek() == Some(&'\n') {
+                self.bump();
+            }
+            self.statement_start = true;
+            return Ok(Some(self.separator(Token::Newline)));
+        } else if c == '\'' {
+            self.bump();
+            if self.keyword() == "Z" {
+                return Ok(Some(Token::ApostropheZ));
+            }
+            return Err(Error::UnknownToken);
         }
 
         let word = self.word();
-        match &*word {
-            "HAI" => return Ok(Some(Token::Hai)),
-            "KTHXBYE" => return Ok(Some(Token::KThxBye)),
-            "BTW" => {
-                loop {
-                    match self.iter.next() {
-                        Some('\n') | None => break,
-                        _ => ()
-                    }
+        // Only words that could be keywords are worth uppercasing
+        let keyword = if self.options.case_insensitive && word.is_ascii() && word.bytes().any(|b| b.is_ascii_lowercase()) {
+            Cow::Owned(word.to_ascii_uppercase())
+        } else {
+            Cow::Borrowed(&*word)
+        };
+        if keyword == self.options.comment {
+            // Leave the line break, it still ends the statement
+            loop {
+                match self.iter.peek() {
+                    None | Some('\n') | Some('\r') => break,
+                    _ => { self.bump(); }
                 }
-                return self.next();
-            },
-            "OBTW" => {
-                loop {
-                    match self.peek() {
-                        None => return Err(Error::UnclosedComment),
-                        Some('T') => {
-                            if self.word() == "TLDR" {
-                                return self.next();
-                            } else {
-                                self.iter.next();
-                            }
-                        },
-                        _ => { self.iter.next(); },
+            }
+            self.statement_start = statement_start;
+            return self.next();
+        } else if keyword == self.options.comment_start {
+            // Only a whole word can end the comment. Anything after it on
+            // the same line is still code.
+            let mut current = String::new();
+            loop {
+                match self.iter.peek().cloned() {
+                    Some(c) if !c.is_whitespace() && c != ',' => {
+                        self.bump();
+                        current.push(c);
+                    },
+                    next => {
+                        if self.is_keyword(&current, &self.options.comment_end) {
+                            self.statement_start = statement_start;
+                            return self.next();
+                        } else if next.is_none() {
+                            return Err(Error::UnclosedComment);
+                        }
+                        self.bump();
+                        current.clear();
                     }
                 }
-            },
+            }
+        }
+        match &*keyword {
+            "HAI" => return Ok(Some(Token::Hai)),
+            "KTHXBYE" => return Ok(Some(Token::KThxBye)),
             "WIN" => return Ok(Some(Token::Value(Value::Troof(true)))),
             "FAIL" => return Ok(Some(Token::Value(Value::Troof(false)))),
             "IT" => return Ok(Some(Token::It)),
             "I" => {
                 let mut clone = self.clone();
-                match &*clone.word() {
-                    "HAS" => if clone.word() == "A" {
+                match &*clone.keyword() {
+                    "HAS" if clone.keyword() == "A" => {
                         *self = clone;
                         return Ok(Some(Token::IHasA));
                     },
@@ -278,14 +571,22 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
                 }
             },
             "ITZ" => return Ok(Some(Token::Itz)),
+            "A" => {
+                let mut clone = self.clone();
+                if type_of(&clone.keyword()).is_some() {
+                    return Ok(Some(Token::A));
+                }
+            },
             "R" => return Ok(Some(Token::R)),
+            "SRS" => return Ok(Some(Token::Srs)),
+            "MAEK" => return Ok(Some(Token::Maek)),
             "SUM" | "DIFF" | "PRODUKT" | "QUOSHUNT" | "MOD" | "BIGGR" | "SMALLR" |
             "BOTH" | "EITHER" | "WON" | "ALL" | "ANY" => {
                 let mut clone = self.clone();
-                match &*clone.word() {
+                match &*clone.keyword() {
                     "OF" => {
                         *self = clone;
-                        return Ok(Some(match &*word {
+                        return Ok(Some(match &*keyword {
                             "SUM" => Token::SumOf,
                             "DIFF" => Token::DiffOf,
                             "PRODUKT" => Token::ProduktOf,
@@ -303,7 +604,7 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
                             _ => unreachable!()
                         }));
                     },
-                    "SAEM" if word == "BOTH" => {
+                    "SAEM" if keyword == "BOTH" => {
                         *self = clone;
                         return Ok(Some(Token::BothSaem));
                     },
@@ -317,14 +618,21 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
             "MKAY" => return Ok(Some(Token::Mkay)),
             "O" => {
                 let mut clone = self.clone();
-                if clone.word() == "RLY?" {
-                    *self = clone;
-                    return Ok(Some(Token::ORly));
+                match &*clone.keyword() {
+                    "RLY?" => {
+                        *self = clone;
+                        return Ok(Some(Token::ORly));
+                    },
+                    "RLY" if self.options.spaced_question_marks && clone.keyword() == "?" => {
+                        *self = clone;
+                        return Ok(Some(Token::ORly));
+                    },
+                    _ => ()
                 }
             },
             "YA" => {
                 let mut clone = self.clone();
-                if clone.word() == "RLY" {
+                if clone.keyword() == "RLY" {
                     *self = clone;
                     return Ok(Some(Token::YaRly));
                 }
@@ -332,24 +640,31 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
             "MEBBE" => return Ok(Some(Token::Mebbe)),
             "NO" => {
                 let mut clone = self.clone();
-                if clone.word() == "WAI" {
+                if clone.keyword() == "WAI" {
                     *self = clone;
                     return Ok(Some(Token::NoWai));
                 }
             },
             "OIC" => return Ok(Some(Token::Oic)),
             "WTF?" => return Ok(Some(Token::Wtf)),
+            "WTF" if self.options.spaced_question_marks => {
+                let mut clone = self.clone();
+                if clone.keyword() == "?" {
+                    *self = clone;
+                    return Ok(Some(Token::Wtf));
+                }
+            },
             "OMG" => return Ok(Some(Token::Omg)),
             "OMGWTF" => return Ok(Some(Token::OmgWtf)),
             "GTFO" => return Ok(Some(Token::Gtfo)),
             "IM" => {
                 let mut clone = self.clone();
-                match &*clone.word() {
-                    "IN" => if clone.word() == "YR" {
+                match &*clone.keyword() {
+                    "IN" if clone.keyword() == "YR" => {
                         *self = clone;
                         return Ok(Some(Token::ImInYr));
                     },
-                    "OUTTA" => if clone.word() == "YR" {
+                    "OUTTA" if clone.keyword() == "YR" => {
                         *self = clone;
                         return Ok(Some(Token::ImOuttaYr));
                     },
@@ -363,55 +678,67 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
             "WILE" => return Ok(Some(Token::Wile)),
             "HOW" => {
                 let mut clone = self.clone();
-                if clone.word() == "IZ" {
-                    if clone.word() == "I" {
-                        *self = clone;
-                        return Ok(Some(Token::HowIzI));
-                    }
+                if clone.keyword() == "IZ" && clone.keyword() == "I" {
+                    *self = clone;
+                    return Ok(Some(Token::HowIzI));
                 }
             },
             "IF" => {
                 let mut clone = self.clone();
-                if clone.word() == "U" {
-                    if clone.word() == "SAY" {
-                        if clone.word() == "SO" {
-                            *self = clone;
-                            return Ok(Some(Token::IfUSaySo));
-                        }
-                    }
+                if clone.keyword() == "U" && clone.keyword() == "SAY" && clone.keyword() == "SO" {
+                    *self = clone;
+                    return Ok(Some(Token::IfUSaySo));
                 }
             },
             "FOUND" => {
                 let mut clone = self.clone();
-                if clone.word() == "YR" {
+                if clone.keyword() == "YR" {
                     *self = clone;
                     return Ok(Some(Token::FoundYr));
                 }
             },
             "VISIBLE" => return Ok(Some(Token::Visible)),
+            "INVISIBLE" => return Ok(Some(Token::Invisible)),
             "!" => return Ok(Some(Token::Exclamation)),
             "GIMMEH" => return Ok(Some(Token::Gimmeh)),
-            _ => ()
+            _ => if let Some(kind) = type_of(&keyword) {
+                return Ok(Some(Token::Type(kind)));
+            }
         }
 
         match c {
-            'a'...'z' |
-            'A'...'Z' |
+            'a'..='z' |
+            'A'..='Z' |
             '_' => {
                 for c in word.chars() {
                     match c {
-                        'a'...'z' |
-                        'A'...'Z' |
-                        '0'...'9' |
+                        'a'..='z' |
+                        'A'..='Z' |
+                        '0'..='9' |
                         '_' => (),
                         c => return Err(Error::InvalidIdent(c))
                     }
                 }
+                if self.options.strict && statement_start {
+                    if let Some(suggestion) = suggest_keyword(&word) {
+                        // An identifier starting a statement is either the
+                        // whole statement or about to be assigned to
+                        match self.clone().next() {
+                            Ok(None) | Ok(Some(Token::Separator)) | Ok(Some(Token::Newline)) |
+                            Ok(Some(Token::Comma)) | Ok(Some(Token::R)) | Ok(Some(Token::ApostropheZ)) => (),
+                            _ => return Err(Error::UnknownKeyword { got: word, suggestion })
+                        }
+                    }
+                }
                 return Ok(Some(Token::Ident(word)));
             },
-            '-' | '0'...'9' => {
+            '-' | '0'..='9' => {
                 if let Ok(num) = word.parse::<i64>() {
                     return Ok(Some(Token::Value(Value::Numbr(num))));
+                } else if word.trim_start_matches('-').chars().all(|c| c.is_ascii_digit()) {
+                    // Integer literal out of range for i64. Don't silently
+                    // turn it into an imprecise NUMBAR.
+                    return Err(Error::InvalidNumber(word));
                 } else if let Ok(num) = word.parse::<f64>() {
                     return Ok(Some(Token::Value(Value::Numbar(num))));
                 }
@@ -426,18 +753,176 @@ impl<I: Iterator<Item = char> + Clone> Tokenizer<I> {
 
 /// Convenience function for reading all tokens from `input`
 pub fn tokenize<I: Iterator<Item = char> + Clone>(input: I) -> Result<Vec<Token>> {
-    let mut tokenizer = Tokenizer { iter: input.peekable() };
+    tokenize_with(input, Options::default())
+}
+/// Check if the start of `input` contains a NUL byte or a lot of control
+/// characters, which doesn't happen in source code
+fn looks_binary<I: Iterator<Item = char>>(input: I) -> bool {
+    let mut total = 0;
+    let mut control = 0;
+    for c in input.take(8192) {
+        if c == '\0' {
+            return true;
+        }
+        if c.is_control() && !c.is_whitespace() {
+            control += 1;
+        }
+        total += 1;
+    }
+    control * 10 > total
+}
+/// Convenience function for reading all tokens from `input` with custom options
+pub fn tokenize_with<I: Iterator<Item = char> + Clone>(input: I, options: Options) -> Result<Vec<Token>> {
+    if options.reject_binary && looks_binary(input.clone()) {
+        return Err(Error::BinaryInput);
+    }
+    let mut tokenizer = Tokenizer::with_options(input, options);
     let mut tokens = Vec::new();
     while let Some(token) = tokenizer.next()? {
         tokens.push(token);
     }
     Ok(tokens)
 }
+/// Read all tokens from `input`, but instead of stopping at the first error,
+/// skip to the next statement and keep going. Useful for linting.
+pub fn tokenize_all(input: &str) -> (Vec<Token>, Vec<Located<Error>>) {
+    let mut tokenizer = Tokenizer::new(input.chars());
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    loop {
+        match tokenizer.next() {
+            Ok(Some(token)) => tokens.push(token),
+            Ok(None) => break,
+            Err(err) => {
+                errors.push(tokenizer.locate()(err));
+                tokenizer.skip_statement();
+            }
+        }
+    }
+    (tokens, errors)
+}
+/// Like `tokenize_str`, but includes the byte range of each token
+pub fn tokenize_spanned(input: &str) -> Result<Vec<Spanned<Token>>> {
+    let mut tokenizer = Tokenizer::new(input.chars());
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_spanned()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+/// Like `tokenize_str`, but includes where each token starts, and where
+/// the error is if there is one
+pub fn tokenize_located(input: &str) -> StdResult<Vec<Located<Token>>, Located<Error>> {
+    let mut tokenizer = Tokenizer::new(input.chars());
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_located() {
+            Ok(Some(token)) => tokens.push(token),
+            Ok(None) => return Ok(tokens),
+            Err(err) => return Err(tokenizer.locate()(err))
+        }
+    }
+}
+/// Like `tokenize_str`, but writes each token to `trace` as it's read,
+/// along with its line and column
+pub fn tokenize_traced<W: io::Write>(input: &str, mut trace: W) -> Result<Vec<Token>> {
+    let mut tokenizer = Tokenizer::new(input.chars());
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_located()? {
+        writeln!(trace, "{}:{}: {:?}", token.line, token.col, token.inner).map_err(Error::TraceError)?;
+        tokens.push(token.inner);
+    }
+    Ok(tokens)
+}
+/// Get the line after `skip` line breaks, counting them and skipping a
+/// byte order mark the same way the tokenizer does
+fn nth_line(src: &str, skip: usize) -> &str {
+    let is_break = |c| c == '\n' || c == '\r';
+    let mut rest = src.strip_prefix('\u{FEFF}').unwrap_or(src);
+    for _ in 0..skip {
+        match rest.find(is_break) {
+            Some(i) => {
+                let len = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+                rest = &rest[i + len..];
+            },
+            None => return ""
+        }
+    }
+    &rest[..rest.find(is_break).unwrap_or(rest.len())]
+}
+/// Render an error together with the line it's on and a caret pointing
+/// at its column, like this:
+///
+/// ```text
+/// error: unknown token
+///  --> 2:9
+///   |
+/// 2 | VISIBLE ?
+///   |         ^
+/// ```
+pub fn format_error<E: fmt::Display>(src: &str, err: &Located<E>) -> String {
+    let source = match err.line.checked_sub(1) {
+        Some(skip) => nth_line(src, skip),
+        None => ""
+    };
+    // Expand tabs so the caret lines up no matter the tab width
+    let mut line = String::new();
+    let mut caret = None;
+    for (i, c) in source.chars().enumerate() {
+        if i + 1 == err.col {
+            caret = Some(line.chars().count());
+        }
+        if c == '\t' {
+            line.push_str(&"    "[line.chars().count() % 4..]);
+        } else {
+            line.push(c);
+        }
+    }
+    let caret = caret.unwrap_or_else(|| line.chars().count());
+    let number = err.line.to_string();
+    let gutter = " ".repeat(number.len());
+    format!(
+        "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}^",
+        err.inner, gutter, err.line, err.col, gutter, number, line, gutter, " ".repeat(caret)
+    )
+}
 /// Convenience function for reading all tokens from `input` from a string
 pub fn tokenize_str(input: &str) -> Result<Vec<Token>> {
     tokenize(input.chars())
 }
 
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonToken {
+    #[serde(flatten)]
+    token: Token,
+    line: usize,
+    col: usize,
+    start: usize,
+    end: usize
+}
+
+/// Read all tokens from `input` as a JSON array, for external tooling.
+/// Each token is an object like
+/// `{"kind":"Ident","value":"X","line":1,"col":9,"start":8,"end":9}`,
+/// where `start` and `end` are byte offsets. Tokens without data have no
+/// `value`, and `Value` tokens hold another `kind`/`value` object.
+#[cfg(feature = "serde")]
+pub fn tokenize_json(input: &str) -> Result<String> {
+    let mut tokenizer = Tokenizer::new(input.chars());
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_located()? {
+        tokens.push(JsonToken {
+            token: token.inner,
+            line: token.line,
+            col: token.col,
+            start: token.offset,
+            end: tokenizer.end
+        });
+    }
+    Ok(::serde_json::to_string(&tokens).expect("tokens always serialize"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -449,6 +934,13 @@ mod tests {
         );
     }
     #[test]
+    fn empty_yarn() {
+        assert_eq!(
+            tokenize_str(r#"VISIBLE """#).unwrap(),
+            &[Token::Visible, Token::Value(Value::Yarn(String::new()))]
+        );
+    }
+    #[test]
     fn interpolation() {
         assert_eq!(
             tokenize_str(r#" ":[SNOWMAN] is :(1F60A). He says:: :{something}" "#).unwrap(),
@@ -459,6 +951,61 @@ mod tests {
         );
     }
     #[test]
+    fn interpolation_bounds() {
+        assert_eq!(
+            tokenize_str(r#""hi :{name}!""#).unwrap(),
+            &[Token::Value(Value::YarnRaw(vec![
+                Interpolate::Str("hi ".to_string()),
+                Interpolate::Var("name".to_string()),
+                Interpolate::Str("!".to_string())
+            ]))]
+        );
+        match tokenize_str(r#""hi :{name"#) {
+            Err(Error::UnclosedString) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        // The string ends before the interpolation does
+        match tokenize_str(r#""hi :{name" "#) {
+            Err(Error::UnclosedInterpolation) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn hex_escape() {
+        assert_eq!(tokenize_str(r#"":(41)""#).unwrap(), &[Token::Value(Value::Yarn("A".to_string()))]);
+        match tokenize_str(r#"":(110000)""#) {
+            Err(Error::InvalidUnicode(ref hex)) if hex == "110000" => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        match tokenize_str(r#"":(41"#) {
+            Err(Error::UnclosedString) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn unicode_names() {
+        assert_eq!(
+            tokenize_str(r#"":[latin capital letter a]:[PILE OF POO]""#).unwrap(),
+            &[Token::Value(Value::Yarn("A💩".to_string()))]
+        );
+        match tokenize_str(r#"":[NOT A REAL CHARACTER]""#) {
+            Err(Error::InvalidUnicode(ref name)) if name == "NOT A REAL CHARACTER" => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn hex_overflow() {
+        match tokenize_str(r#" ":(FFFFFFFFFFFFFFFFF)" "#) {
+            Err(Error::InvalidNumber(ref hex)) if hex == "FFFFFFFFFFFFFFFFF" => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        // There are no radix prefixes for number literals at all
+        match tokenize_str("0xFFFFFFFFFFFFFFFFF") {
+            Err(Error::InvalidNumber(_)) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
     fn primitives() {
         assert_eq!(
             tokenize_str("1, -5, 2.3, WIN, FAIL").unwrap(),
@@ -472,6 +1019,24 @@ mod tests {
         );
     }
     #[test]
+    fn big_numbers() {
+        assert_eq!(
+            tokenize_str("9223372036854775807, 1.5e300").unwrap(),
+            &[
+                Token::Value(Value::Numbr(9223372036854775807)), Token::Separator,
+                Token::Value(Value::Numbar(1.5e300))
+            ]
+        );
+        match tokenize_str("99999999999999999999") {
+            Err(Error::InvalidNumber(ref num)) if num == "99999999999999999999" => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        match tokenize_str("-99999999999999999999") {
+            Err(Error::InvalidNumber(_)) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
     fn assign() {
         assert_eq!(
             tokenize_str("I HAS A VAR ITZ 12           BTW this is a comment").unwrap(),
@@ -483,6 +1048,89 @@ mod tests {
         );
     }
     #[test]
+    fn inline_comment() {
+        assert_eq!(
+            tokenize_str("VISIBLE X BTW c\nVISIBLE Y").unwrap(),
+            &[Token::Visible, Token::Ident("X".to_string()), Token::Separator,
+              Token::Visible, Token::Ident("Y".to_string())]
+        );
+        assert_eq!(
+            tokenize_str("SUM OF 2 AN 4 BTW add them\r\nBTW\n").unwrap(),
+            &[Token::SumOf, Token::Value(Value::Numbr(2)), Token::An, Token::Value(Value::Numbr(4)),
+              Token::Separator, Token::Separator]
+        );
+    }
+    #[test]
+    fn trailing_comment() {
+        assert_eq!(
+            tokenize("VISIBLE 1 BTW trailing".chars()).unwrap(),
+            &[Token::Visible, Token::Value(Value::Numbr(1))]
+        );
+        assert_eq!(
+            tokenize("OBTW a TLDR VISIBLE 1 BTW trailing".chars()).unwrap(),
+            &[Token::Visible, Token::Value(Value::Numbr(1))]
+        );
+    }
+    #[test]
+    fn bukkit() {
+        assert_eq!(
+            tokenize_str("I HAS A arr ITZ A BUKKIT").unwrap(),
+            &[Token::IHasA, Token::Ident("arr".to_string()), Token::Itz, Token::A, Token::Type(Type::Bukkit)]
+        );
+        assert_eq!(
+            tokenize_str("VISIBLE arr'Z key, arr 'Z key").unwrap(),
+            &[
+                Token::Visible, Token::Ident("arr".to_string()), Token::ApostropheZ, Token::Ident("key".to_string()),
+                Token::Separator,
+                Token::Ident("arr".to_string()), Token::ApostropheZ, Token::Ident("key".to_string())
+            ]
+        );
+        assert!(tokenize_str("arr'X key").is_err());
+        // A is only a keyword in front of a type
+        assert_eq!(tokenize_str("A R 1").unwrap(), &[Token::Ident("A".to_string()), Token::R, Token::Value(Value::Numbr(1))]);
+    }
+    #[test]
+    fn exclamation() {
+        let expected = &[
+            Token::Visible, Token::Ident("a".to_string()), Token::An, Token::Ident("b".to_string()), Token::Exclamation
+        ];
+        assert_eq!(tokenize_str("VISIBLE a AN b!").unwrap(), expected);
+        assert_eq!(tokenize_str("VISIBLE a AN b !").unwrap(), expected);
+        assert_eq!(
+            tokenize_str("VISIBLE 1!, VISIBLE SMOOSH a MKAY!\n").unwrap(),
+            &[Token::Visible, Token::Value(Value::Numbr(1)), Token::Exclamation, Token::Separator,
+              Token::Visible, Token::Smoosh, Token::Ident("a".to_string()), Token::Mkay, Token::Exclamation,
+              Token::Separator]
+        );
+        assert!(tokenize_str("VISIBLE a!b").is_err());
+    }
+    #[test]
+    fn srs() {
+        assert_eq!(
+            tokenize_str("VISIBLE SRS x").unwrap(),
+            &[Token::Visible, Token::Srs, Token::Ident("x".to_string())]
+        );
+    }
+    #[test]
+    fn crlf() {
+        assert_eq!(
+            tokenize_str("I HAS A X\r\nVISIBLE X\r\n").unwrap(),
+            &[Token::IHasA, Token::Ident("X".to_string()), Token::Separator,
+              Token::Visible, Token::Ident("X".to_string()), Token::Separator]
+        );
+        assert_eq!(
+            tokenize_str("VISIBLE X\rVISIBLE X BTW comment\r\nVISIBLE X").unwrap(),
+            tokenize_str("VISIBLE X\nVISIBLE X BTW comment\nVISIBLE X").unwrap()
+        );
+    }
+    #[test]
+    fn unicode_whitespace() {
+        assert_eq!(
+            tokenize_str("SUM\x0cOF\x0c1\u{a0}AN\x0b2\n").unwrap(),
+            &[Token::SumOf, Token::Value(Value::Numbr(1)), Token::An, Token::Value(Value::Numbr(2)), Token::Separator]
+        );
+    }
+    #[test]
     fn sum_of() {
         assert_eq!(
             tokenize_str("SUM OF OBTW hi TLDR 2 AN 4").unwrap(),
@@ -490,6 +1138,291 @@ mod tests {
         );
     }
     #[test]
+    fn custom_comments() {
+        // Markers can also come from somewhere at runtime, like a config file
+        let end = String::from("*/");
+        let options = Options {
+            comment: "//".into(),
+            comment_start: "/*".into(),
+            comment_end: end.into(),
+            ..Options::default()
+        };
+        assert_eq!(
+            tokenize_with("/* multi\nline */ VISIBLE BTW // BTW is just an identifier now".chars(), options).unwrap(),
+            &[Token::Visible, Token::Ident("BTW".to_string())]
+        );
+    }
+    #[test]
+    fn strict() {
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        match tokenize_with("VISBLE X".chars(), options.clone()) {
+            Err(Error::UnknownKeyword { ref got, suggestion: "VISIBLE" }) if got == "VISBLE" => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(
+            tokenize_with("VISIBLE X AN VAR".chars(), options.clone()).unwrap(),
+            &[Token::Visible, Token::Ident("X".to_string()), Token::An, Token::Ident("VAR".to_string())]
+        );
+        // Names close to a keyword are fine where an identifier can go
+        let ident = |name: &str| Token::Ident(name.to_string());
+        assert_eq!(
+            tokenize_with("NUMBRS R 1\nBTW hi\nVISIBLE NUMBRS YARNZ\nYARNZ'Z 0 R 2, NUMBRS".chars(), options.clone()).unwrap(),
+            &[ident("NUMBRS"), Token::R, Token::Value(Value::Numbr(1)), Token::Separator, Token::Separator,
+              Token::Visible, ident("NUMBRS"), ident("YARNZ"), Token::Separator,
+              ident("YARNZ"), Token::ApostropheZ, Token::Value(Value::Numbr(0)), Token::R, Token::Value(Value::Numbr(2)),
+              Token::Separator, ident("NUMBRS")]
+        );
+        match tokenize_with("OBTW comment TLDR VISBLE X".chars(), options) {
+            Err(Error::UnknownKeyword { ref got, suggestion: "VISIBLE" }) if got == "VISBLE" => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(tokenize_str("VISBLE").unwrap(), &[Token::Ident("VISBLE".to_string())]);
+    }
+    #[test]
+    fn spaced_question_marks() {
+        let options = Options {
+            spaced_question_marks: true,
+            ..Options::default()
+        };
+        for code in &["O RLY?, WTF?", "O RLY ?, WTF ?"] {
+            assert_eq!(
+                tokenize_with(code.chars(), options.clone()).unwrap(),
+                &[Token::ORly, Token::Separator, Token::Wtf]
+            );
+        }
+        assert!(tokenize_str("O RLY ?").is_err());
+        assert!(tokenize_str("WTF ?").is_err());
+    }
+    #[test]
+    fn detailed_separators() {
+        let options = Options {
+            detailed_separators: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            tokenize_with("VISIBLE 1, VISIBLE 2\r\nGTFO\n".chars(), options).unwrap(),
+            &[Token::Visible, Token::Value(Value::Numbr(1)), Token::Comma,
+              Token::Visible, Token::Value(Value::Numbr(2)), Token::Newline,
+              Token::Gtfo, Token::Newline]
+        );
+        assert_eq!(tokenize_str("GTFO, GTFO\n").unwrap(), &[Token::Gtfo, Token::Separator, Token::Gtfo, Token::Separator]);
+    }
+    #[test]
+    fn case_insensitive() {
+        let options = Options {
+            case_insensitive: true,
+            ..Options::default()
+        };
+        assert_eq!(tokenize_with("visible 1".chars(), options.clone()).unwrap(), tokenize_str("VISIBLE 1").unwrap());
+        assert_eq!(tokenize_str("visible 1").unwrap(), &[Token::Ident("visible".to_string()), Token::Value(Value::Numbr(1))]);
+        assert_eq!(
+            tokenize_with("Sum Of x an 1 btw hi\nI has a Foo itz a numbr, foo'z bar R o rly?\nobtw\n tldr".chars(), options.clone()).unwrap(),
+            &[Token::SumOf, Token::Ident("x".to_string()), Token::An, Token::Value(Value::Numbr(1)), Token::Separator,
+              Token::IHasA, Token::Ident("Foo".to_string()), Token::Itz, Token::A, Token::Type(Type::Numbr), Token::Separator,
+              Token::Ident("foo".to_string()), Token::ApostropheZ, Token::Ident("bar".to_string()), Token::R, Token::ORly,
+              Token::Separator]
+        );
+        // YARNs keep their case
+        assert_eq!(tokenize_with("\"visible\"".chars(), options.clone()).unwrap(), &[Token::Value(Value::Yarn("visible".to_string()))]);
+        // Only ASCII letters are folded, even though ſ uppercases to S
+        for code in &["ſum of 1 an 2", "visible ıt"] {
+            match tokenize_with(code.chars(), options.clone()) {
+                Err(Error::UnknownToken) => (),
+                result => panic!("unexpected result for {:?}: {:?}", code, result)
+            }
+        }
+        match tokenize_with("I HAS A straße".chars(), options) {
+            Err(Error::InvalidIdent('ß')) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
+    fn binary_input() {
+        let options = Options {
+            reject_binary: true,
+            ..Options::default()
+        };
+        match tokenize_with("HAI 1.2\0\0\x7fELF".chars(), options.clone()) {
+            Err(Error::BinaryInput) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        match tokenize_with("\x01\x02\x03 VISIBLE X".chars(), options.clone()) {
+            Err(Error::BinaryInput) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(
+            tokenize_with("VISIBLE X\t\r\n".chars(), options).unwrap(),
+            &[Token::Visible, Token::Ident("X".to_string()), Token::Separator]
+        );
+    }
+    #[test]
+    fn tokenize_all_errors() {
+        let (tokens, errors) = tokenize_all("VISIBLE X\nVISIBLE Y$ AN Z\r\nI HAS A X ITZ 1\n  X R 1.2.3, VISIBLE X");
+        assert_eq!(tokens, &[
+            Token::Visible, Token::Ident("X".to_string()), Token::Separator,
+            Token::Visible, Token::Separator,
+            Token::IHasA, Token::Ident("X".to_string()), Token::Itz, Token::Value(Value::Numbr(1)), Token::Separator,
+            Token::Ident("X".to_string()), Token::R, Token::Separator,
+            Token::Visible, Token::Ident("X".to_string())
+        ]);
+        let errors: Vec<_> = errors.into_iter().map(|err| (err.line, err.col, err.inner.to_string())).collect();
+        assert_eq!(errors, &[
+            (2, 9, "invalid character in identifier: $".to_string()),
+            (4, 7, "invalid number: \"1.2.3\"".to_string())
+        ]);
+    }
+    #[test]
+    fn format_errors() {
+        let src = "HAI 1.2\n\tVISIBLE ?\nKTHXBYE";
+        let (_, errors) = tokenize_all(src);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].col), (2, 10));
+        assert_eq!(
+            format_error(src, &errors[0]),
+            "error: unknown token\n \
+             --> 2:10\n  \
+             |\n\
+             2 |     VISIBLE ?\n  \
+             |             ^"
+        );
+        let located = Located { line: 10, col: 4, offset: 0, inner: "at the end" };
+        assert!(format_error(&format!("{}abc", "\n".repeat(9)), &located).ends_with("10 | abc\n   |    ^"));
+        let located = Located { line: 0, col: 0, offset: 0, inner: "nowhere" };
+        assert!(format_error("VISIBLE 1", &located).ends_with("0 | \n  | ^"));
+
+        // Lines are split and the BOM skipped like the tokenizer does
+        for src in &["HAI 1.2\rVISIBLE ?\r\nKTHXBYE", "\u{FEFF}HAI 1.2\r\nVISIBLE ?"] {
+            let (_, errors) = tokenize_all(src);
+            assert_eq!((errors[0].line, errors[0].col), (2, 9));
+            assert!(format_error(src, &errors[0]).ends_with("2 | VISIBLE ?\n  |         ^"), "{:?}", src);
+        }
+        let (_, errors) = tokenize_all("\u{FEFF}VISIBLE ?");
+        assert!(format_error("\u{FEFF}VISIBLE ?", &errors[0]).ends_with("1 | VISIBLE ?\n  |         ^"));
+    }
+    #[test]
+    fn into_inner() {
+        let mut tokenizer = Tokenizer::new("SUM OF 1 AN 2 ...and then some HTML <b>".chars());
+        assert_eq!(tokenizer.next().unwrap(), Some(Token::SumOf));
+        assert_eq!(tokenizer.next().unwrap(), Some(Token::Value(Value::Numbr(1))));
+        assert_eq!(tokenizer.into_inner().collect::<String>(), "AN 2 ...and then some HTML <b>");
+    }
+    #[test]
+    fn version() {
+        // The version is just a number, so a comment can come right after it
+        assert_eq!(
+            tokenize_str("HAI 1.2 BTW start\nKTHXBYE extra").unwrap(),
+            &[Token::Hai, Token::Value(Value::Numbar(1.2)), Token::Separator, Token::KThxBye, Token::Ident("extra".to_string())]
+        );
+    }
+    #[test]
+    fn bom() {
+        assert_eq!(tokenize_str("\u{FEFF}VISIBLE 1").unwrap(), &[Token::Visible, Token::Value(Value::Numbr(1))]);
+        assert_eq!(tokenize_spanned("\u{FEFF}VISIBLE").unwrap(), &[Spanned { node: Token::Visible, start: 3, end: 10 }]);
+        assert!(tokenize_str("VISIBLE 1\n\u{FEFF}VISIBLE 2").is_err());
+    }
+    #[test]
+    fn offsets() {
+        let mut tokenizer = Tokenizer::new("\"☃\" X\nVISIBLE".chars());
+        let mut tokens = Vec::new();
+        while let Some(token) = tokenizer.next_located().unwrap() {
+            tokens.push((token.line, token.col, token.offset));
+        }
+        assert_eq!(tokens, &[(1, 1, 0), (1, 5, 6), (1, 6, 7), (2, 1, 8)]);
+        assert_eq!(tokenizer.offset(), 15);
+
+        // Comments before a token aren't part of it
+        let mut tokenizer = Tokenizer::new("OBTW c TLDR VISIBLE X BTW hi\nVISIBLE Y".chars());
+        let mut tokens = Vec::new();
+        while let Some(token) = tokenizer.next_located().unwrap() {
+            tokens.push((token.line, token.col, token.offset));
+        }
+        assert_eq!(tokens, &[(1, 13, 12), (1, 21, 20), (1, 29, 28), (2, 1, 29), (2, 9, 37)]);
+        let (_, errors) = tokenize_all("BTW hi\nVISIBLE ?");
+        assert_eq!(errors.iter().map(|err| (err.line, err.col)).collect::<Vec<_>>(), &[(2, 9)]);
+
+        let tokens = tokenize_located("BTW hi\nVISIBLE X").unwrap();
+        assert_eq!(tokens.iter().map(|token| (token.line, token.col)).collect::<Vec<_>>(), &[(1, 7), (2, 1), (2, 9)]);
+        match tokenize_located("VISIBLE 1\nVISIBLE 1.2.3") {
+            Err(Located { line: 2, col: 9, inner: Error::InvalidNumber(_), .. }) => (),
+            result => panic!("{:?}", result.map(|_| ()))
+        }
+    }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn json() {
+        assert_eq!(
+            tokenize_json("VISIBLE X, VISIBLE \"☃\" 1").unwrap(),
+            "[{\"kind\":\"Visible\",\"line\":1,\"col\":1,\"start\":0,\"end\":7},\
+            {\"kind\":\"Ident\",\"value\":\"X\",\"line\":1,\"col\":9,\"start\":8,\"end\":9},\
+            {\"kind\":\"Separator\",\"line\":1,\"col\":10,\"start\":9,\"end\":10},\
+            {\"kind\":\"Visible\",\"line\":1,\"col\":12,\"start\":11,\"end\":18},\
+            {\"kind\":\"Value\",\"value\":{\"kind\":\"Yarn\",\"value\":\"☃\"},\"line\":1,\"col\":20,\"start\":19,\"end\":24},\
+            {\"kind\":\"Value\",\"value\":{\"kind\":\"Numbr\",\"value\":1},\"line\":1,\"col\":24,\"start\":25,\"end\":26}]"
+        );
+        assert!(tokenize_json("VISIBLE 1.2.3").is_err());
+        assert_eq!(
+            tokenize_json("OBTW c TLDR VISIBLE BTW hi\n").unwrap(),
+            "[{\"kind\":\"Visible\",\"line\":1,\"col\":13,\"start\":12,\"end\":19},\
+            {\"kind\":\"Separator\",\"line\":1,\"col\":27,\"start\":26,\"end\":27}]"
+        );
+    }
+    #[test]
+    fn traced() {
+        let mut trace = Vec::new();
+        let tokens = tokenize_traced("VISIBLE 1\n  GIMMEH X", &mut trace).unwrap();
+        assert_eq!(tokens, tokenize_str("VISIBLE 1\n  GIMMEH X").unwrap());
+        assert_eq!(
+            String::from_utf8(trace).unwrap(),
+            "1:1: Visible\n\
+             1:9: Value(Numbr(1))\n\
+             1:10: Separator\n\
+             2:3: Gimmeh\n\
+             2:10: Ident(\"X\")\n"
+        );
+    }
+    #[test]
+    fn spans() {
+        assert_eq!(tokenize_spanned("VISIBLE X").unwrap(), &[
+            Spanned { node: Token::Visible, start: 0, end: 7 },
+            Spanned { node: Token::Ident("X".to_string()), start: 8, end: 9 }
+        ]);
+        let spans: Vec<_> = tokenize_spanned("I HAS A X ITZ \"☃ b\"!\n")
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.start, token.end))
+            .collect();
+        assert_eq!(spans, &[(0, 7), (8, 9), (10, 13), (14, 21), (21, 22), (22, 23)]);
+        let spans: Vec<_> = tokenize_spanned("OBTW c TLDR VISIBLE X BTW hi\nVISIBLE Y")
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.start, token.end))
+            .collect();
+        assert_eq!(spans, &[(12, 19), (20, 21), (28, 29), (29, 36), (37, 38)]);
+    }
+    #[test]
+    fn multiline_comments() {
+        assert_eq!(
+            tokenize_str("OBTW I GOT IT, STOP TLDR VISIBLE 1").unwrap(),
+            &[Token::Visible, Token::Value(Value::Numbr(1))]
+        );
+        assert_eq!(
+            tokenize_str("OBTW\n    TTLDR TLDRS T\nTLDR\nVISIBLE 1").unwrap(),
+            &[Token::Separator, Token::Visible, Token::Value(Value::Numbr(1))]
+        );
+        assert_eq!(
+            tokenize_str("VISIBLE 1 OBTW same line TLDR, VISIBLE 2").unwrap(),
+            &[Token::Visible, Token::Value(Value::Numbr(1)), Token::Separator,
+              Token::Visible, Token::Value(Value::Numbr(2))]
+        );
+        match tokenize_str("OBTW this is xTLDR") {
+            Err(Error::UnclosedComment) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+    #[test]
     fn orly() {
         assert_eq!(
             tokenize_str("\
@@ -1,60 +1,149 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, cmp::Ordering, convert::TryFrom, fmt, mem};
+
+/// Parse a YARN as a number. Surrounding whitespace is fine, but
+/// anything else that isn't part of the number isn't.
+fn parse_yarn<T: ::std::str::FromStr>(yarn: &str) -> Option<T> {
+    yarn.trim().parse().ok()
+}
+
+/// How deeply nested BUKKITs the recursive operations here handle, so
+/// malicious input can't overflow the stack
+const MAX_DEPTH: usize = 256;
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(tag = "kind", content = "value"))]
 pub enum Interpolate {
     Str(String),
     Var(String)
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Type {
+    Noob,
+    Troof,
+    Numbr,
+    Numbar,
+    Yarn,
+    Bukkit
+}
+impl Type {
+    /// The value a variable declared with `ITZ A <type>` starts out with
+    pub fn default_value(self) -> Value {
+        match self {
+            Type::Noob => Value::Noob,
+            Type::Troof => Value::Troof(false),
+            Type::Numbr => Value::Numbr(0),
+            Type::Numbar => Value::Numbar(0.0),
+            Type::Yarn => Value::Yarn(String::new()),
+            Type::Bukkit => Value::Bukkit(Vec::new())
+        }
+    }
+}
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Type::Noob => "NOOB",
+            Type::Troof => "TROOF",
+            Type::Numbr => "NUMBR",
+            Type::Numbar => "NUMBAR",
+            Type::Yarn => "YARN",
+            Type::Bukkit => "BUKKIT"
+        })
+    }
+}
+
+/// Returned by the `TryFrom<Value>` conversions
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display = "cannot convert {} to {}", from, to)]
+pub struct CastError {
+    pub from: Type,
+    pub to: Type
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(tag = "kind", content = "value"))]
 pub enum Value {
+    #[default]
     Noob,
     Yarn(String),
+    /// A YARN literal containing `:{var}` interpolations. The tokenizer
+    /// emits these and the evaluator turns them into a `Yarn` with
+    /// `interpolate` once the variables are known.
     YarnRaw(Vec<Interpolate>),
     Numbr(i64),
     Numbar(f64),
-    Troof(bool)
+    Troof(bool),
+    /// Slots in insertion order
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_slots"))]
+    Bukkit(Vec<(String, Value)>)
 }
-impl Default for Value {
-    fn default() -> Self {
-        Value::Noob
+#[cfg(feature = "serde")]
+fn serialize_slots<S: ::serde::Serializer>(slots: &[(String, Value)], serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::{ser::Error, Serialize};
+    // Every level checks all levels below it again, but that's still
+    // cheaper than overflowing the stack
+    if slots.iter().any(|(_, value)| value.depth() >= MAX_DEPTH) {
+        return Err(S::Error::custom(format!("BUKKIT nested more than {} levels deep", MAX_DEPTH)));
     }
+    slots.serialize(serializer)
 }
 impl Value {
-    pub fn cast_yarn(&self) -> Option<Cow<str>> {
+    /// Shorthand for `Value::Noob`
+    pub fn noob() -> Self {
+        Value::Noob
+    }
+    /// The type of the value
+    pub fn kind(&self) -> Type {
+        match *self {
+            Value::Noob => Type::Noob,
+            Value::Yarn(_) | Value::YarnRaw(_) => Type::Yarn,
+            Value::Numbr(_) => Type::Numbr,
+            Value::Numbar(_) => Type::Numbar,
+            Value::Troof(_) => Type::Troof,
+            Value::Bukkit(_) => Type::Bukkit
+        }
+    }
+    pub fn cast_yarn(&self) -> Option<Cow<'_, str>> {
         match *self {
             Value::Noob => None,
             Value::Yarn(ref inner) => Some(Cow::Borrowed(inner)),
             Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
             Value::Numbr(n) => Some(Cow::Owned(n.to_string())),
-            Value::Numbar(n) => Some(Cow::Owned(n.to_string())),
+            // The spec says NUMBARs are printed with two decimals
+            Value::Numbar(n) => Some(Cow::Owned(format!("{:.2}", n))),
             Value::Troof(true) => Some(Cow::Borrowed("WIN")),
-            Value::Troof(false) => Some(Cow::Borrowed("FAIL"))
+            Value::Troof(false) => Some(Cow::Borrowed("FAIL")),
+            Value::Bukkit(_) => Some(Cow::Borrowed("<BUKKIT>"))
         }
     }
     pub fn cast_numbr(&self) -> Option<i64> {
         match *self {
             Value::Noob => None,
-            Value::Yarn(ref inner) => Some(inner.parse().unwrap_or(0)),
+            Value::Yarn(ref inner) => parse_yarn(inner),
             Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
             Value::Numbr(n) => Some(n),
+            // Truncates toward zero like the reference implementation. Out of
+            // range NUMBARs saturate to the smallest/biggest NUMBR, NaN is 0.
             Value::Numbar(n) => Some(n as i64),
-            Value::Troof(b) => Some(b as i64)
+            Value::Troof(b) => Some(b as i64),
+            Value::Bukkit(_) => None
         }
     }
     pub fn cast_numbar(&self) -> Option<f64> {
         match *self {
             Value::Noob => None,
-            Value::Yarn(ref inner) => Some(inner.parse().unwrap_or(0.0)),
+            Value::Yarn(ref inner) => parse_yarn(inner),
             Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
             Value::Numbr(n) => Some(n as f64),
             Value::Numbar(n) => Some(n),
-            Value::Troof(b) => Some(b as i64 as f64)
+            Value::Troof(b) => Some(b as i64 as f64),
+            Value::Bukkit(_) => None
         }
     }
     pub fn is_numbr(&self) -> bool {
         match *self {
-            Value::Yarn(ref inner) => inner.parse::<u64>().is_ok(),
+            Value::Yarn(ref inner) => parse_yarn::<i64>(inner).is_some(),
             Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
             Value::Numbr(_) => true,
             _ => false
@@ -62,44 +151,192 @@ impl Value {
     }
     pub fn is_numbar(&self) -> bool {
         match *self {
-            Value::Yarn(ref inner) => !self.is_numbr() && inner.parse::<f64>().is_ok(),
+            Value::Yarn(ref inner) => !self.is_numbr() && parse_yarn::<f64>(inner).is_some(),
             Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
             Value::Numbar(_) => true,
             _ => false
         }
     }
+    /// Promote two operands of a math operator to the same type: NUMBARs if
+    /// either one is a NUMBAR, NUMBRs otherwise. Returns None if either
+    /// of them isn't numeric.
+    pub fn coerce_numeric_pair(&self, other: &Self) -> Option<(Value, Value)> {
+        let numeric = |value: &Value| match *value {
+            Value::Yarn(_) => value.is_numbr() || value.is_numbar(),
+            _ => true
+        };
+        if !numeric(self) || !numeric(other) {
+            return None;
+        }
+        if self.is_numbar() || other.is_numbar() {
+            Some((Value::Numbar(self.cast_numbar()?), Value::Numbar(other.cast_numbar()?)))
+        } else {
+            Some((Value::Numbr(self.cast_numbr()?), Value::Numbr(other.cast_numbr()?)))
+        }
+    }
+    /// Compare two values numerically, promoting both to NUMBARs if either
+    /// one is a NUMBAR. Returns None if either of them isn't numeric, or if
+    /// either one is NaN.
+    pub fn numeric_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.coerce_numeric_pair(other)? {
+            (Value::Numbr(x), Value::Numbr(y)) => Some(x.cmp(&y)),
+            (Value::Numbar(x), Value::Numbar(y)) => x.partial_cmp(&y),
+            _ => unreachable!("coerce_numeric_pair returned mismatched types")
+        }
+    }
     pub fn cast_troof(&self) -> bool {
         match *self {
             Value::Noob => false,
-            Value::Yarn(ref inner) => inner.is_empty(),
+            Value::Yarn(ref inner) => !inner.is_empty(),
             Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
-            Value::Numbr(n) => n == 0,
-            Value::Numbar(n) => n == 0.0,
-            Value::Troof(b) => b
+            Value::Numbr(n) => n != 0,
+            Value::Numbar(n) => n != 0.0,
+            Value::Troof(b) => b,
+            Value::Bukkit(ref slots) => !slots.is_empty()
         }
     }
-    /// Check if the values are equal (used by the BOTH SAEM operator).
-    /// This does auto-coercion, unlike the PartialEq trait implementation.
-    pub fn equals(&self, other: &Self) -> bool {
-        if let Value::YarnRaw(_) = *self  { panic!("yarn not interpolated yet"); }
-        if let Value::YarnRaw(_) = *other { panic!("yarn not interpolated yet"); }
-
-        if let Value::Noob = *self {
-            if let Value::Noob = *other {
-                return true;
+    /// The slots of a BUKKIT, in insertion order
+    pub fn as_bukkit(&self) -> Option<&[(String, Value)]> {
+        match *self {
+            Value::Bukkit(ref slots) => Some(slots),
+            _ => None
+        }
+    }
+    /// How many levels of BUKKITs deep the value goes: 0 for anything that
+    /// isn't a BUKKIT, 1 for a BUKKIT of other values, and so on. This
+    /// doesn't recurse, so it's safe to call on any value.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut level = vec![self];
+        loop {
+            let slots: Vec<_> = level.iter().filter_map(|value| value.as_bukkit()).collect();
+            if slots.is_empty() {
+                break depth;
             }
+            depth += 1;
+            level = slots.iter().flat_map(|slots| slots.iter().map(|(_, value)| value)).collect();
         }
-        if let Value::Troof(b) = *self {
-            if let Value::Troof(b2) = *other {
-                return b == b2;
+    }
+    /// Look up a slot of a BUKKIT. Returns None if there's no
+    /// such slot, or if this isn't a BUKKIT.
+    pub fn get_slot(&self, key: &str) -> Option<&Value> {
+        self.as_bukkit()?.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+    /// Like `get_slot`, but allows changing the slot's value
+    pub fn get_slot_mut(&mut self, key: &str) -> Option<&mut Value> {
+        match *self {
+            Value::Bukkit(ref mut slots) => slots.iter_mut().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None
+        }
+    }
+    /// Set a slot of a BUKKIT, returning the value it replaced. New slots
+    /// go at the end, and keys stay unique. Fails if this isn't a BUKKIT.
+    pub fn set_slot<K: Into<String>>(&mut self, key: K, value: Value) -> Result<Option<Value>, CastError> {
+        let kind = self.kind();
+        let slots = match *self {
+            Value::Bukkit(ref mut slots) => slots,
+            _ => return Err(CastError { from: kind, to: Type::Bukkit })
+        };
+        let key = key.into();
+        match slots.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, slot)) => Ok(Some(mem::replace(slot, value))),
+            None => {
+                slots.push((key, value));
+                Ok(None)
             }
         }
-        if self.is_numbar() || other.is_numbar() {
-            self.cast_numbar() == other.cast_numbar()
-        } else if self.is_numbr() || other.is_numbr() {
-            self.cast_numbr() == other.cast_numbr()
-        } else {
-            self.cast_yarn() == other.cast_yarn()
+    }
+    /// Remove a slot of a BUKKIT, keeping the others in order. Returns
+    /// None if there's no such slot, or if this isn't a BUKKIT.
+    pub fn remove_slot(&mut self, key: &str) -> Option<Value> {
+        match *self {
+            Value::Bukkit(ref mut slots) => {
+                let i = slots.iter().position(|(name, _)| name == key)?;
+                Some(slots.remove(i).1)
+            },
+            _ => None
+        }
+    }
+    /// Canonicalize the value to a string, for using it as a map key
+    /// without needing to hash NUMBARs. Keys are mostly the YARN casts,
+    /// except that NUMBARs keep their full precision: `Numbar(1.001)` gives
+    /// `"1.001"`, but always with at least two decimals, so `Numbr(3)`
+    /// gives `"3"` and `Numbar(3.0)` gives `"3.00"`, two distinct keys. A
+    /// YARN is its own key, so `Yarn("3")` and `Numbr(3)` do share one.
+    /// NOOBs and BUKKITs have no key.
+    pub fn as_key(&self) -> Option<String> {
+        match *self {
+            Value::Noob | Value::Bukkit(_) => None,
+            // Also matches -0.0, which is the same number
+            Value::Numbar(0.0) => Some("0.00".to_string()),
+            Value::Numbar(n) => {
+                // The shortest form that reads back as the same number
+                let mut key = format!("{:?}", n);
+                if let Some(dot) = key.find('.') {
+                    let decimals = key.len() - dot - 1;
+                    for _ in decimals..2 {
+                        key.push('0');
+                    }
+                }
+                Some(key)
+            },
+            _ => self.cast_yarn().map(Cow::into_owned)
+        }
+    }
+    /// Cast the value to `kind`, like `MAEK` does.
+    /// Returns None if there's no such conversion.
+    pub fn cast(&self, kind: Type) -> Option<Value> {
+        match kind {
+            Type::Noob => Some(Value::Noob),
+            Type::Troof => Some(Value::Troof(self.cast_troof())),
+            Type::Numbr => self.cast_numbr().map(Value::Numbr),
+            Type::Numbar => self.cast_numbar().map(Value::Numbar),
+            Type::Yarn => self.cast_yarn().map(|yarn| Value::Yarn(yarn.into_owned())),
+            Type::Bukkit => match *self {
+                Value::Bukkit(_) => Some(self.clone()),
+                _ => None
+            }
+        }
+    }
+    /// Like the Debug output, but using LOLCODE's type names, e.g. `NUMBR(5)`.
+    /// Mostly useful for readable test failures. BUKKITs nested more than
+    /// 256 levels deep are cut off as `BUKKIT(...)`.
+    pub fn debug_lolcode(&self) -> String {
+        self.debug_lolcode_at(0)
+    }
+    fn debug_lolcode_at(&self, depth: usize) -> String {
+        match *self {
+            Value::Noob => "NOOB".to_string(),
+            Value::Yarn(ref inner) => format!("YARN({:?})", inner),
+            Value::YarnRaw(ref parts) => format!("YARN({:?})", parts),
+            Value::Numbr(n) => format!("NUMBR({})", n),
+            Value::Numbar(n) => format!("NUMBAR({:?})", n),
+            Value::Troof(b) => format!("TROOF({})", if b { "WIN" } else { "FAIL" }),
+            Value::Bukkit(_) if depth >= MAX_DEPTH => "BUKKIT(...)".to_string(),
+            Value::Bukkit(ref slots) => {
+                let slots: Vec<_> = slots.iter()
+                    .map(|(key, value)| format!("{:?}: {}", key, value.debug_lolcode_at(depth + 1)))
+                    .collect();
+                format!("BUKKIT({})", slots.join(", "))
+            }
+        }
+    }
+    /// Check if the values are equal (used by the BOTH SAEM operator).
+    /// NUMBRs and NUMBARs compare by value, promoting the NUMBR if only one
+    /// side is a NUMBAR. Other than that there's no casting: values of
+    /// different types are never equal, so `BOTH SAEM 3 AN "3"` is FAIL.
+    /// BUKKITs are values rather than references, so they're equal if they
+    /// have the same slots with equal values, in any order. That recurses,
+    /// so check the `depth` of untrusted BUKKITs first.
+    pub fn equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::YarnRaw(_), _) | (_, Value::YarnRaw(_)) => panic!("yarn not interpolated yet"),
+            (&Value::Numbr(x), &Value::Numbar(y)) | (&Value::Numbar(y), &Value::Numbr(x)) => x as f64 == y,
+            (Value::Bukkit(one), Value::Bukkit(two)) => one.len() == two.len() && one.iter().all(|(key, value)| {
+                two.iter().any(|(key2, value2)| key == key2 && value.equals(value2))
+            }),
+            // The same type, or different types which are never equal
+            _ => self == other
         }
     }
     /// Interpolate a YARN value at evaluation time.
@@ -138,4 +375,458 @@ impl Value {
         }
         None
     }
+    /// Encode the value in a compact binary format: a tag byte followed
+    /// by the payload. Lengths are LEB128 varints, numbers are little endian.
+    /// Returns None for BUKKITs nested deeper than `from_bytes` accepts.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        if self.depth() > MAX_DEPTH {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        self.write_bytes(&mut bytes);
+        Some(bytes)
+    }
+    /// Decode a value encoded with `to_bytes`.
+    /// Returns None if the input is malformed or has trailing bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Value> {
+        let mut bytes = bytes;
+        let value = Self::read_bytes(&mut bytes, 0)?;
+        if bytes.is_empty() { Some(value) } else { None }
+    }
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        fn write_len(out: &mut Vec<u8>, mut len: usize) {
+            while len >= 0x80 {
+                out.push(len as u8 | 0x80);
+                len >>= 7;
+            }
+            out.push(len as u8);
+        }
+        fn write_str(out: &mut Vec<u8>, string: &str) {
+            write_len(out, string.len());
+            out.extend_from_slice(string.as_bytes());
+        }
+        match *self {
+            Value::Noob => out.push(0),
+            Value::Yarn(ref inner) => {
+                out.push(1);
+                write_str(out, inner);
+            },
+            Value::YarnRaw(_) => panic!("yarn not interpolated yet"),
+            Value::Numbr(n) => {
+                out.push(2);
+                out.extend_from_slice(&n.to_le_bytes());
+            },
+            Value::Numbar(n) => {
+                out.push(3);
+                out.extend_from_slice(&n.to_bits().to_le_bytes());
+            },
+            Value::Troof(b) => out.push(if b { 5 } else { 4 }),
+            Value::Bukkit(ref slots) => {
+                out.push(6);
+                write_len(out, slots.len());
+                for (key, value) in slots {
+                    write_str(out, key);
+                    value.write_bytes(out);
+                }
+            }
+        }
+    }
+    fn read_bytes(bytes: &mut &[u8], depth: usize) -> Option<Value> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if bytes.len() < len {
+                return None;
+            }
+            let (start, rest) = bytes.split_at(len);
+            *bytes = rest;
+            Some(start)
+        }
+        fn read_len(bytes: &mut &[u8]) -> Option<usize> {
+            let mut len = 0usize;
+            let mut shift = 0;
+            loop {
+                let byte = take(bytes, 1)?[0];
+                if shift >= 64 {
+                    return None;
+                }
+                len |= usize::from(byte & 0x7F).checked_shl(shift)?;
+                if byte & 0x80 == 0 {
+                    return Some(len);
+                }
+                shift += 7;
+            }
+        }
+        fn read_str(bytes: &mut &[u8]) -> Option<String> {
+            let len = read_len(bytes)?;
+            String::from_utf8(take(bytes, len)?.to_vec()).ok()
+        }
+        fn read_u64(bytes: &mut &[u8]) -> Option<u64> {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(take(bytes, 8)?);
+            Some(u64::from_le_bytes(buf))
+        }
+        Some(match take(bytes, 1)?[0] {
+            0 => Value::Noob,
+            1 => Value::Yarn(read_str(bytes)?),
+            2 => Value::Numbr(read_u64(bytes)? as i64),
+            3 => Value::Numbar(f64::from_bits(read_u64(bytes)?)),
+            4 => Value::Troof(false),
+            5 => Value::Troof(true),
+            6 => {
+                if depth >= MAX_DEPTH {
+                    return None;
+                }
+                let len = read_len(bytes)?;
+                // Don't trust the length for preallocating
+                let mut slots = Vec::new();
+                for _ in 0..len {
+                    let key = read_str(bytes)?;
+                    slots.push((key, Self::read_bytes(bytes, depth + 1)?));
+                }
+                Value::Bukkit(slots)
+            },
+            _ => return None
+        })
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Numbr(n)
+    }
+}
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Numbar(n)
+    }
+}
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Troof(b)
+    }
+}
+impl From<String> for Value {
+    fn from(yarn: String) -> Self {
+        Value::Yarn(yarn)
+    }
+}
+impl<'a> From<&'a str> for Value {
+    fn from(yarn: &'a str) -> Self {
+        Value::Yarn(yarn.to_string())
+    }
+}
+
+// These mirror the cast_* functions, except NOOB is always an error
+macro_rules! try_from_value {
+    ($($target:ty => $kind:ident, |$value:ident| $cast:expr;)*) => {
+        $(impl TryFrom<Value> for $target {
+            type Error = CastError;
+            fn try_from($value: Value) -> Result<Self, Self::Error> {
+                let error = CastError { from: $value.kind(), to: Type::$kind };
+                match $value {
+                    Value::Noob => Err(error),
+                    _ => $cast.ok_or(error)
+                }
+            }
+        })*
+    }
+}
+try_from_value! {
+    i64 => Numbr, |value| value.cast_numbr();
+    f64 => Numbar, |value| value.cast_numbar();
+    bool => Troof, |value| Some(value.cast_troof());
+    String => Yarn, |value| value.cast_yarn().map(Cow::into_owned);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64;
+
+    #[test]
+    fn numeric_cmp() {
+        assert_eq!(Value::Numbr(3).numeric_cmp(&Value::Numbar(3.0)), Some(Ordering::Equal));
+        assert_eq!(Value::Numbr(2).numeric_cmp(&Value::Numbar(2.5)), Some(Ordering::Less));
+        assert_eq!(Value::Numbar(-0.5).numeric_cmp(&Value::Numbr(-1)), Some(Ordering::Greater));
+        assert_eq!(Value::Yarn("10".to_string()).numeric_cmp(&Value::Numbr(9)), Some(Ordering::Greater));
+        assert_eq!(Value::Numbr(i64::MAX).numeric_cmp(&Value::Numbr(i64::MAX - 1)), Some(Ordering::Greater));
+        assert_eq!(Value::Numbar(f64::NAN).numeric_cmp(&Value::Numbr(1)), None);
+        assert_eq!(Value::Yarn("cat".to_string()).numeric_cmp(&Value::Numbr(1)), None);
+        assert_eq!(Value::Numbr(1).numeric_cmp(&Value::Noob), None);
+    }
+    #[test]
+    fn bukkit_accessors() {
+        let mut value = Value::Bukkit(vec![
+            ("NAME".to_string(), Value::from("Bob")),
+            ("AGE".to_string(), Value::Numbr(7)),
+            ("PET".to_string(), Value::Bukkit(vec![("NAME".to_string(), Value::from("Fluffy"))]))
+        ]);
+        assert_eq!(value.as_bukkit().map(|slots| slots.len()), Some(3));
+        assert_eq!(value.get_slot("AGE"), Some(&Value::Numbr(7)));
+        assert_eq!(value.get_slot("PET").and_then(|pet| pet.get_slot("NAME")), Some(&Value::from("Fluffy")));
+        assert_eq!(value.get_slot("age"), None);
+
+        assert_eq!(value.set_slot("FAV", Value::Troof(true)), Ok(None));
+        assert_eq!(value.get_slot("FAV"), Some(&Value::Troof(true)));
+        assert_eq!(value.as_bukkit().unwrap()[3].0, "FAV");
+        // Setting an existing slot replaces it rather than adding another
+        assert_eq!(value.set_slot("AGE", Value::Numbr(8)), Ok(Some(Value::Numbr(7))));
+        assert_eq!(value.as_bukkit().map(|slots| slots.len()), Some(4));
+        *value.get_slot_mut("AGE").unwrap() = Value::Numbr(9);
+        assert_eq!(value.get_slot("AGE"), Some(&Value::Numbr(9)));
+
+        assert_eq!(value.remove_slot("AGE"), Some(Value::Numbr(9)));
+        assert_eq!(value.remove_slot("AGE"), None);
+        let keys: Vec<_> = value.as_bukkit().unwrap().iter().map(|(key, _)| &**key).collect();
+        assert_eq!(keys, &["NAME", "PET", "FAV"]);
+
+        let mut number = Value::Numbr(1);
+        assert_eq!(number.as_bukkit(), None);
+        assert_eq!(number.get_slot("AGE"), None);
+        assert_eq!(number.get_slot_mut("AGE"), None);
+        assert_eq!(number.set_slot("AGE", Value::Noob), Err(CastError { from: Type::Numbr, to: Type::Bukkit }));
+        assert_eq!(number.remove_slot("AGE"), None);
+    }
+    #[test]
+    fn depth() {
+        fn nested(depth: usize) -> Value {
+            (0..depth).fold(Value::Numbr(1), |inner, _| Value::Bukkit(vec![("X".to_string(), inner)]))
+        }
+        assert_eq!(Value::Numbr(1).depth(), 0);
+        assert_eq!(Value::Bukkit(Vec::new()).depth(), 1);
+        assert_eq!(nested(3).depth(), 3);
+        let uneven = Value::Bukkit(vec![("A".to_string(), Value::Noob), ("B".to_string(), nested(2))]);
+        assert_eq!(uneven.depth(), 3);
+
+        let fine = nested(MAX_DEPTH);
+        assert_eq!(Value::from_bytes(&fine.to_bytes().unwrap()).as_ref(), Some(&fine));
+        assert!(!fine.debug_lolcode().contains("..."));
+        let deeper = Value::Bukkit(vec![("X".to_string(), fine.clone())]);
+        assert_eq!(deeper.to_bytes(), None);
+        assert!(deeper.debug_lolcode().contains("BUKKIT(...)"));
+        #[cfg(feature = "serde")] {
+            assert!(::serde_json::to_string(&fine).is_ok());
+            assert!(::serde_json::to_string(&deeper).is_err());
+        }
+        // Built by hand, to not need a huge value
+        let mut too_deep = Vec::new();
+        for _ in 0..=MAX_DEPTH {
+            too_deep.extend_from_slice(&[6, 1, 1, b'X']);
+        }
+        too_deep.push(0);
+        assert_eq!(Value::from_bytes(&too_deep), None);
+        assert_eq!(Value::from_bytes(&too_deep[4..]).map(|value| value.depth()), Some(MAX_DEPTH));
+    }
+    #[test]
+    fn keys() {
+        assert_eq!(Value::Numbr(3).as_key(), Some("3".to_string()));
+        assert_eq!(Value::Numbar(3.0).as_key(), Some("3.00".to_string()));
+        assert_eq!(Value::Numbar(-0.125).as_key(), Some("-0.125".to_string()));
+        assert_eq!(Value::Numbar(2.5).as_key(), Some("2.50".to_string()));
+        assert_eq!(Value::Numbar(-0.0).as_key(), Value::Numbar(0.0).as_key());
+        assert_eq!(Value::Numbar(1e20).as_key(), Some("1e20".to_string()));
+        // Precision past the second decimal still matters
+        assert_eq!(Value::Numbar(1.001).as_key(), Some("1.001".to_string()));
+        assert_ne!(Value::Numbar(1.001).as_key(), Value::Numbar(1.002).as_key());
+        assert_eq!(Value::Yarn("cat".to_string()).as_key(), Some("cat".to_string()));
+        assert_eq!(Value::Troof(true).as_key(), Some("WIN".to_string()));
+        assert_eq!(Value::Troof(false).as_key(), Some("FAIL".to_string()));
+        assert_eq!(Value::Noob.as_key(), None);
+        assert_eq!(Value::Bukkit(Vec::new()).as_key(), None);
+        assert_ne!(Value::Numbr(3).as_key(), Value::Numbar(3.0).as_key());
+        assert_eq!(Value::Numbr(3).as_key(), Value::Yarn("3".to_string()).as_key());
+    }
+    #[test]
+    fn conversions() {
+        assert_eq!(Value::from(42), Value::Numbr(42));
+        assert_eq!(Value::from(2.5), Value::Numbar(2.5));
+        assert_eq!(Value::from(true), Value::Troof(true));
+        assert_eq!(Value::from("hi"), Value::Yarn("hi".to_string()));
+        assert_eq!(Value::from("hi".to_string()), Value::Yarn("hi".to_string()));
+        let value: Value = (-1).into();
+        assert_eq!(value, Value::Numbr(-1));
+        assert_eq!(Value::noob(), Value::Noob);
+
+        assert_eq!(i64::try_from(Value::Numbar(2.9)), Ok(2));
+        assert_eq!(i64::try_from(Value::from(" 12 ")), Ok(12));
+        assert_eq!(f64::try_from(Value::Numbr(3)), Ok(3.0));
+        assert_eq!(f64::try_from(Value::Troof(true)), Ok(1.0));
+        assert_eq!(bool::try_from(Value::from("")), Ok(false));
+        assert_eq!(bool::try_from(Value::Numbar(0.5)), Ok(true));
+        assert_eq!(String::try_from(Value::Numbar(1.0)), Ok("1.00".to_string()));
+        assert_eq!(String::try_from(Value::Troof(false)), Ok("FAIL".to_string()));
+
+        assert_eq!(i64::try_from(Value::from("cat")), Err(CastError { from: Type::Yarn, to: Type::Numbr }));
+        assert_eq!(f64::try_from(Value::Bukkit(Vec::new())), Err(CastError { from: Type::Bukkit, to: Type::Numbar }));
+        assert_eq!(bool::try_from(Value::Noob), Err(CastError { from: Type::Noob, to: Type::Troof }));
+        let err = String::try_from(Value::Noob).unwrap_err();
+        assert_eq!(err.to_string(), "cannot convert NOOB to YARN");
+    }
+    #[test]
+    fn bytes() {
+        let values = [
+            Value::Noob,
+            Value::Yarn(String::new()),
+            Value::Yarn("straße ☃".repeat(20)),
+            Value::Numbr(0),
+            Value::Numbr(i64::MIN),
+            Value::Numbr(i64::MAX),
+            Value::Numbar(-1.5),
+            Value::Numbar(f64::INFINITY),
+            Value::Troof(false),
+            Value::Troof(true),
+            Value::Bukkit(Vec::new()),
+            Value::Bukkit(vec![
+                ("a".to_string(), Value::Numbr(1)),
+                ("nested".to_string(), Value::Bukkit(vec![
+                    ("b".to_string(), Value::Yarn("hi".to_string())),
+                    ("c".to_string(), Value::Bukkit(vec![("d".to_string(), Value::Noob)]))
+                ])),
+                ("e".to_string(), Value::Troof(true))
+            ])
+        ];
+        for value in &values {
+            let bytes = value.to_bytes().unwrap();
+            assert_eq!(Value::from_bytes(&bytes).as_ref(), Some(value));
+            // Cutting it short or adding anything is invalid
+            assert_eq!(Value::from_bytes(&bytes[..bytes.len() - 1]), None);
+            let mut longer = bytes.clone();
+            longer.push(0);
+            assert_eq!(Value::from_bytes(&longer), None);
+        }
+        assert_eq!(Value::Numbr(1).to_bytes().unwrap(), &[2, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Value::Yarn("hi".to_string()).to_bytes().unwrap(), b"\x01\x02hi");
+        match Value::from_bytes(&Value::Numbar(f64::NAN).to_bytes().unwrap()) {
+            Some(Value::Numbar(n)) if n.is_nan() => (),
+            value => panic!("unexpected value: {:?}", value)
+        }
+        assert_eq!(Value::from_bytes(&[]), None);
+        assert_eq!(Value::from_bytes(&[7]), None);
+        assert_eq!(Value::from_bytes(&[1, 2, 0xFF, 0xFF]), None);
+    }
+    #[test]
+    fn parse_yarns() {
+        let yarn = |yarn: &str| Value::Yarn(yarn.to_string());
+        assert_eq!(yarn(" 42 ").cast_numbr(), Some(42));
+        assert_eq!(yarn("\t+5\n").cast_numbr(), Some(5));
+        assert_eq!(yarn("-7").cast_numbr(), Some(-7));
+        assert_eq!(yarn("4 2").cast_numbr(), None);
+        assert_eq!(yarn("abc").cast_numbr(), None);
+        assert_eq!(yarn("").cast_numbr(), None);
+        assert_eq!(yarn("1.5").cast_numbr(), None);
+
+        assert_eq!(yarn(" 1.5 ").cast_numbar(), Some(1.5));
+        assert_eq!(yarn("+5").cast_numbar(), Some(5.0));
+        assert_eq!(yarn("4 2").cast_numbar(), None);
+        assert_eq!(yarn("abc").cast_numbar(), None);
+
+        assert!(yarn(" 42 ").is_numbr());
+        assert!(yarn(" 1.5 ").is_numbar());
+        assert!(!yarn("4 2").is_numbr() && !yarn("4 2").is_numbar());
+    }
+    #[test]
+    fn cross_type_equality() {
+        let yarn = |yarn: &str| Value::Yarn(yarn.to_string());
+        assert!(Value::Noob.equals(&Value::Noob));
+        assert!(yarn("3").equals(&yarn("3")));
+        assert!(!yarn("3").equals(&Value::Numbr(3)));
+        assert!(!Value::Numbar(3.0).equals(&yarn("3.0")));
+        assert!(!Value::Troof(true).equals(&yarn("WIN")));
+        assert!(!Value::Troof(false).equals(&Value::Numbr(0)));
+        assert!(!Value::Noob.equals(&Value::Troof(false)));
+        assert!(!Value::Noob.equals(&yarn("")));
+    }
+    #[test]
+    fn numbar_truncation() {
+        for &(n, expected) in &[
+            (2.7, 2),
+            (-2.7, -2),
+            (0.999, 0),
+            (-0.5, 0),
+            (1e30, i64::MAX),
+            (-1e30, i64::MIN),
+            (f64::INFINITY, i64::MAX),
+            (f64::NEG_INFINITY, i64::MIN),
+            (f64::NAN, 0)
+        ] {
+            assert_eq!(Value::Numbar(n).cast_numbr(), Some(expected), "{}", n);
+        }
+    }
+    #[test]
+    fn numeric_equality() {
+        assert!(Value::Numbr(3).equals(&Value::Numbar(3.0)));
+        assert!(Value::Numbar(3.0).equals(&Value::Numbr(3)));
+        assert!(!Value::Numbr(3).equals(&Value::Numbar(3.5)));
+        assert!(!Value::Numbar(f64::NAN).equals(&Value::Numbar(f64::NAN)));
+        assert!(!Value::Numbar(f64::NAN).equals(&Value::Numbr(0)));
+    }
+    #[test]
+    fn debug_lolcode() {
+        assert_eq!(Value::Noob.debug_lolcode(), "NOOB");
+        assert_eq!(Value::Numbr(5).debug_lolcode(), "NUMBR(5)");
+        assert_eq!(Value::Numbar(1.0).debug_lolcode(), "NUMBAR(1.0)");
+        assert_eq!(Value::Troof(true).debug_lolcode(), "TROOF(WIN)");
+        assert_eq!(Value::Yarn("hi".to_string()).debug_lolcode(), "YARN(\"hi\")");
+        assert_eq!(
+            Value::Bukkit(vec![
+                ("a".to_string(), Value::Numbr(1)),
+                ("b".to_string(), Value::Bukkit(Vec::new()))
+            ]).debug_lolcode(),
+            "BUKKIT(\"a\": NUMBR(1), \"b\": BUKKIT())"
+        );
+    }
+    #[test]
+    fn coerce_numeric_pair() {
+        assert_eq!(
+            Value::Numbr(1).coerce_numeric_pair(&Value::Numbr(2)),
+            Some((Value::Numbr(1), Value::Numbr(2)))
+        );
+        assert_eq!(
+            Value::Numbr(1).coerce_numeric_pair(&Value::Numbar(2.5)),
+            Some((Value::Numbar(1.0), Value::Numbar(2.5)))
+        );
+        assert_eq!(
+            Value::Numbar(0.5).coerce_numeric_pair(&Value::Numbar(2.5)),
+            Some((Value::Numbar(0.5), Value::Numbar(2.5)))
+        );
+        assert_eq!(
+            Value::Yarn("-3".to_string()).coerce_numeric_pair(&Value::Troof(true)),
+            Some((Value::Numbr(-3), Value::Numbr(1)))
+        );
+        assert_eq!(
+            Value::Yarn("1.5".to_string()).coerce_numeric_pair(&Value::Numbr(1)),
+            Some((Value::Numbar(1.5), Value::Numbar(1.0)))
+        );
+        assert_eq!(Value::Noob.coerce_numeric_pair(&Value::Numbr(1)), None);
+        assert_eq!(Value::Numbr(1).coerce_numeric_pair(&Value::Yarn("abc".to_string())), None);
+        assert_eq!(Value::Bukkit(Vec::new()).coerce_numeric_pair(&Value::Numbr(1)), None);
+    }
+    #[test]
+    fn numbar_yarn() {
+        assert_eq!(Value::Numbar(f64::consts::PI).cast_yarn().as_deref(), Some("3.14"));
+        assert_eq!(Value::Numbar(3.0).cast_yarn().as_deref(), Some("3.00"));
+        assert_eq!(Value::Numbar(0.1 + 0.2).cast_yarn().as_deref(), Some("0.30"));
+        assert_eq!(Value::Numbr(3).cast_yarn().as_deref(), Some("3"));
+    }
+    #[test]
+    fn truthiness() {
+        assert!(!Value::Noob.cast_troof());
+        assert!(!Value::Numbr(0).cast_troof());
+        assert!(Value::Numbr(5).cast_troof());
+        assert!(!Value::Numbar(0.0).cast_troof());
+        assert!(Value::Numbar(-0.5).cast_troof());
+        assert!(!Value::Yarn(String::new()).cast_troof());
+        assert!(Value::Yarn("x".to_string()).cast_troof());
+        // Any non-empty YARN is WIN, even ones that look falsy
+        assert!(Value::Yarn("FAIL".to_string()).cast_troof());
+    }
+    #[test]
+    fn empty_bukkit() {
+        let bukkit = Type::Bukkit.default_value();
+        assert_eq!(bukkit, Value::Bukkit(Vec::new()));
+        assert_eq!(bukkit.cast_yarn().as_deref(), Some("<BUKKIT>"));
+        assert_eq!(bukkit.cast_numbr(), None);
+        assert!(!bukkit.cast_troof());
+        assert!(bukkit.equals(&Value::Bukkit(Vec::new())));
+        assert!(!bukkit.equals(&Value::Yarn("<BUKKIT>".to_string())));
+    }
 }
@@ -0,0 +1,66 @@
+use eval::{EvalParams, Interpreter};
+use parser;
+use std::io;
+use tokenizer;
+use wasm_bindgen::prelude::*;
+use Error;
+
+fn located(line: usize, col: usize, err: Error) -> String {
+    format!("{}:{}: {}", line, col, err)
+}
+fn run_code(code: &str, stdin: &str) -> Result<String, String> {
+    let tokens = tokenizer::tokenize_located(code).map_err(|err| located(err.line, err.col, Error::TokenizeError(err.inner)))?;
+    let parsed = parser::parse_located(tokens, parser::Options::default())
+        .map_err(|err| located(err.line, err.col, Error::ParseError(err.inner)))?;
+
+    let mut output = Vec::new();
+    {
+        let mut interpreter = Interpreter::new(EvalParams::new(io::Cursor::new(stdin), &mut output), parsed);
+        if let Err(err) = interpreter.run() {
+            let (line, col) = interpreter.position().expect("parse_located gives every statement a position");
+            return Err(located(line, col, Error::EvalError(err)));
+        }
+    }
+    Ok(String::from_utf8(output).expect("Program (somehow) returned non-utf8 data"))
+}
+
+/// Run `code` without any input, returning everything it printed.
+/// Errors are thrown as JS exceptions.
+#[wasm_bindgen]
+pub fn run(code: &str) -> Result<String, JsValue> {
+    run_with_input(code, "")
+}
+
+/// Like `run`, but GIMMEH reads lines from `stdin`
+#[wasm_bindgen]
+pub fn run_with_input(code: &str, stdin: &str) -> Result<String, JsValue> {
+    run_code(code, stdin).map_err(|err| JsValue::from_str(&err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rng::CLOCK_READS;
+    use std::cell::Cell;
+
+    #[test]
+    fn hello_world() {
+        // std has no clock on wasm, so running code mustn't need one
+        let reads = CLOCK_READS.with(Cell::get);
+        assert_eq!(run_code(include_str!("../tests/hello-world.lol"), ""), Ok("HAI WORLD\n".to_string()));
+        assert_eq!(run_code("GIMMEH X, VISIBLE X", "hi\n"), Ok("hi\n".to_string()));
+        assert_eq!(
+            run_code("HAI 1.2\nVISIBLE 1.2.3", ""),
+            Err("2:9: tokenize error: invalid number: \"1.2.3\"".to_string())
+        );
+        assert_eq!(
+            run_code("HAI 1.2\nI HAS A 1", ""),
+            Err("2:9: parse error: expected identifier".to_string())
+        );
+        assert_eq!(
+            run_code("WIN, O RLY?\nYA RLY, VISIBLE QUOSHUNT OF 1 AN 0\nOIC", ""),
+            Err("2:9: eval error: attempt to divide by zero".to_string())
+        );
+        assert_eq!(CLOCK_READS.with(Cell::get), reads);
+    }
+}
@@ -0,0 +1,17 @@
+extern crate lci;
+
+use lci::Error;
+
+#[test]
+fn run_entry_points() {
+    lci::run_str("HAI 1.2\nVISIBLE \"HAI WORLD\"\nKTHXBYE").expect("Running test failed");
+    lci::run_file("tests/hello-world.lol").expect("Running test failed");
+    match lci::run_file("tests/does-not-exist.lol") {
+        Err(Error::IoError(_)) => (),
+        _ => panic!("Running test failed (in a bad way)")
+    }
+    assert_eq!(
+        lci::run_str_capture(include_str!("hello-world.lol")).expect("Running test failed"),
+        "HAI WORLD\n"
+    );
+}